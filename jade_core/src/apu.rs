@@ -0,0 +1,1092 @@
+//! Register level model of the Game Boy audio processing unit.
+//!
+//! The APU lives at 0xFF10..=0xFF3F: four channels (two squares, a
+//! programmable wave and a noise generator), the master control registers
+//! and 16 bytes of wave RAM. The frame sequencer runs at 512 Hz and clocks
+//! the length counters, the channel 1 sweep and the volume envelopes.
+//!
+//! The model specific quirks follow Blargg's dmg_sound and cgb_sound
+//! suites: the DMG corrupts wave RAM when channel 3 is retriggered while
+//! reading it and keeps the length counters writable while powered off,
+//! the CGB exposes the digital channel outputs through PCM12 and PCM34.
+
+use crate::{
+    state::{Snapshot, StateError, StateReader, StateWriter},
+    vgm::VgmRecorder,
+};
+
+/// Clock of the CPU in T-cycles per second.
+pub const CPU_CLOCK: u32 = 4_194_304;
+/// Number of T-cycles between two frame sequencer steps (512 Hz).
+const FRAME_SEQUENCER_PERIOD: u32 = CPU_CLOCK / 512;
+
+pub const NR10: u16 = 0xFF10;
+pub const NR11: u16 = 0xFF11;
+pub const NR12: u16 = 0xFF12;
+pub const NR13: u16 = 0xFF13;
+pub const NR14: u16 = 0xFF14;
+pub const NR21: u16 = 0xFF16;
+pub const NR22: u16 = 0xFF17;
+pub const NR23: u16 = 0xFF18;
+pub const NR24: u16 = 0xFF19;
+pub const NR30: u16 = 0xFF1A;
+pub const NR31: u16 = 0xFF1B;
+pub const NR32: u16 = 0xFF1C;
+pub const NR33: u16 = 0xFF1D;
+pub const NR34: u16 = 0xFF1E;
+pub const NR41: u16 = 0xFF20;
+pub const NR42: u16 = 0xFF21;
+pub const NR43: u16 = 0xFF22;
+pub const NR44: u16 = 0xFF23;
+pub const NR50: u16 = 0xFF24;
+pub const NR51: u16 = 0xFF25;
+pub const NR52: u16 = 0xFF26;
+pub const WAVE_RAM_START: u16 = 0xFF30;
+pub const WAVE_RAM_END: u16 = 0xFF3F;
+/// CGB only, digital output of channels 1 and 2.
+pub const PCM12: u16 = 0xFF76;
+/// CGB only, digital output of channels 3 and 4.
+pub const PCM34: u16 = 0xFF77;
+
+/// Waveforms of the four duty cycles, played from the most significant bit.
+const DUTY_PATTERNS: [u8; 4] = [0b0000_0001, 0b1000_0001, 0b1000_0111, 0b0111_1110];
+
+/// Bits that always read back as 1, indexed from NR10.
+const READ_MASKS: [u8; 0x17] = [
+    0x80, 0x3F, 0x00, 0xFF, 0xBF, // NR10-NR14
+    0xFF, 0x3F, 0x00, 0xFF, 0xBF, // unused, NR21-NR24
+    0x7F, 0xFF, 0x9F, 0xFF, 0xBF, // NR30-NR34
+    0xFF, 0xFF, 0x00, 0x00, 0xBF, // unused, NR41-NR44
+    0x00, 0x00, 0x70, // NR50-NR52
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    Square1,
+    Square2,
+    Wave,
+    Noise,
+}
+impl Channel {
+    pub const ALL: [Channel; 4] = [
+        Channel::Square1,
+        Channel::Square2,
+        Channel::Wave,
+        Channel::Noise,
+    ];
+}
+
+/// Snapshot of what a channel is currently playing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChannelState {
+    /// Whether the channel is enabled and its DAC is on.
+    pub active: bool,
+    /// The 11 bit period for the square and wave channels, NR43 for noise.
+    pub period: u16,
+    /// Output frequency in Hz. For the noise channel this is the LFSR clock.
+    pub frequency: f32,
+    /// Current volume on a 0-15 scale.
+    pub volume: u8,
+    /// Duty cycle index (0-3), only for the square channels.
+    pub duty: Option<u8>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct LengthCounter {
+    enabled: bool,
+    counter: u16,
+    max: u16,
+}
+impl LengthCounter {
+    fn new(max: u16) -> Self {
+        Self {
+            max,
+            ..Default::default()
+        }
+    }
+    fn load(&mut self, value: u16) {
+        self.counter = self.max - value;
+    }
+    /// Returns true when the counter just expired and the channel must stop.
+    fn clock(&mut self) -> bool {
+        if self.enabled && self.counter > 0 {
+            self.counter -= 1;
+            self.counter == 0
+        } else {
+            false
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct Envelope {
+    initial: u8,
+    increase: bool,
+    pace: u8,
+    volume: u8,
+    timer: u8,
+    /// Cleared once the volume reaches 0 or 15.
+    running: bool,
+}
+impl Envelope {
+    fn write(&mut self, value: u8) {
+        self.initial = value >> 4;
+        self.increase = value & 0x08 != 0;
+        self.pace = value & 0x07;
+    }
+    /// Writing NRx2 while the channel plays changes the volume directly
+    /// ("zombie mode").
+    fn zombie_write(&mut self, value: u8) {
+        let increase = value & 0x08 != 0;
+        if self.pace == 0 && self.running {
+            self.volume += 1;
+        } else if !self.increase {
+            self.volume += 2;
+        }
+        if increase != self.increase {
+            self.volume = 16u8.wrapping_sub(self.volume);
+        }
+        self.volume &= 0x0F;
+        self.write(value);
+    }
+    fn dac_enabled(&self) -> bool {
+        self.initial != 0 || self.increase
+    }
+    fn trigger(&mut self) {
+        self.volume = self.initial;
+        self.timer = self.pace;
+        self.running = true;
+    }
+    fn clock(&mut self) {
+        if self.pace == 0 {
+            return;
+        }
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer == 0 {
+            self.timer = self.pace;
+            if self.increase && self.volume < 15 {
+                self.volume += 1;
+            } else if !self.increase && self.volume > 0 {
+                self.volume -= 1;
+            } else {
+                self.running = false;
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct Sweep {
+    pace: u8,
+    negate: bool,
+    step: u8,
+    timer: u8,
+    enabled: bool,
+    shadow: u16,
+    /// Set once a period was computed in negate mode since the last trigger.
+    negated: bool,
+}
+impl Sweep {
+    /// Returns true when leaving negate mode after a negated calculation,
+    /// which disables channel 1.
+    fn write(&mut self, value: u8) -> bool {
+        self.pace = (value >> 4) & 0x07;
+        let negate = value & 0x08 != 0;
+        let disable = self.negate && !negate && self.negated;
+        self.negate = negate;
+        self.step = value & 0x07;
+        disable
+    }
+    fn next_period(&mut self) -> u16 {
+        let delta = self.shadow >> self.step;
+        if self.negate {
+            self.negated = true;
+            self.shadow - delta
+        } else {
+            self.shadow + delta
+        }
+    }
+    fn reload_timer(&mut self) {
+        self.timer = if self.pace == 0 { 8 } else { self.pace };
+    }
+}
+
+/// Step a frequency `timer` reloaded with `period` by `cycles` T-cycles,
+/// returning how many times it expired.
+fn advance_timer(timer: &mut u32, period: u32, cycles: u32) -> u32 {
+    if cycles < *timer {
+        *timer -= cycles;
+        return 0;
+    }
+    let remaining = cycles - *timer;
+    *timer = period - remaining % period;
+    1 + remaining / period
+}
+
+#[derive(Debug, Clone)]
+struct SquareChannel {
+    enabled: bool,
+    duty: u8,
+    period: u16,
+    length: LengthCounter,
+    envelope: Envelope,
+    timer: u32,
+    position: u8,
+}
+impl Default for SquareChannel {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            duty: 0,
+            period: 0,
+            length: LengthCounter::new(64),
+            envelope: Envelope::default(),
+            timer: 0,
+            position: 0,
+        }
+    }
+}
+impl SquareChannel {
+    fn timer_period(&self) -> u32 {
+        (2048 - self.period as u32) * 4
+    }
+    fn tick(&mut self, cycles: u32) {
+        let period = self.timer_period();
+        let steps = advance_timer(&mut self.timer, period, cycles);
+        self.position = ((self.position as u32 + steps) % 8) as u8;
+    }
+    fn amplitude(&self) -> u8 {
+        let high = (DUTY_PATTERNS[self.duty as usize] >> (7 - self.position)) & 1 != 0;
+        if self.enabled && high {
+            self.envelope.volume
+        } else {
+            0
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct WaveChannel {
+    enabled: bool,
+    dac: bool,
+    output_level: u8,
+    period: u16,
+    length: LengthCounter,
+    timer: u32,
+    /// Index of the current 4 bit sample in wave RAM.
+    position: u8,
+    sample: u8,
+}
+impl Default for WaveChannel {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            dac: false,
+            output_level: 0,
+            period: 0,
+            length: LengthCounter::new(256),
+            timer: 0,
+            position: 0,
+            sample: 0,
+        }
+    }
+}
+impl WaveChannel {
+    fn timer_period(&self) -> u32 {
+        (2048 - self.period as u32) * 2
+    }
+    /// Whether wave RAM was read during the last cycle.
+    fn just_read(&self) -> bool {
+        self.enabled && self.timer == self.timer_period()
+    }
+    fn amplitude(&self) -> u8 {
+        if self.enabled {
+            self.sample >> [4, 0, 1, 2][self.output_level as usize]
+        } else {
+            0
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct NoiseChannel {
+    enabled: bool,
+    polynomial: u8,
+    length: LengthCounter,
+    envelope: Envelope,
+    timer: u32,
+    lfsr: u16,
+}
+impl Default for NoiseChannel {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            polynomial: 0,
+            length: LengthCounter::new(64),
+            envelope: Envelope::default(),
+            timer: 0,
+            lfsr: 0x7FFF,
+        }
+    }
+}
+impl NoiseChannel {
+    fn timer_period(&self) -> u32 {
+        let divisor = match self.polynomial & 0x07 {
+            0 => 8,
+            divisor => divisor as u32 * 16,
+        };
+        divisor << (self.polynomial >> 4)
+    }
+    fn tick(&mut self, cycles: u32) {
+        // Shifts 14 and 15 stop the LFSR.
+        if self.polynomial >> 4 >= 14 {
+            return;
+        }
+        let period = self.timer_period();
+        for _ in 0..advance_timer(&mut self.timer, period, cycles) {
+            let bit = (self.lfsr ^ (self.lfsr >> 1)) & 1;
+            self.lfsr = (self.lfsr >> 1) | (bit << 14);
+            if self.polynomial & 0x08 != 0 {
+                self.lfsr = (self.lfsr & !0x40) | (bit << 6);
+            }
+        }
+    }
+    fn amplitude(&self) -> u8 {
+        if self.enabled && self.lfsr & 1 == 0 {
+            self.envelope.volume
+        } else {
+            0
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Apu {
+    /// Whether the CGB behaviour is emulated instead of the DMG one.
+    cgb: bool,
+    powered: bool,
+    square1: SquareChannel,
+    sweep: Sweep,
+    square2: SquareChannel,
+    wave: WaveChannel,
+    noise: NoiseChannel,
+    wave_ram: [u8; 16],
+    /// Last value written to each register from NR10 to NR52.
+    registers: [u8; 0x17],
+    /// Next step the frame sequencer will run.
+    frame_sequencer_step: u8,
+    frame_sequencer_counter: u32,
+    /// T-cycles elapsed since power on, used to timestamp the VGM writes.
+    cycles: u64,
+    vgm: Option<VgmRecorder>,
+}
+impl Apu {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// An APU with the CGB quirks instead of the DMG ones.
+    pub fn new_cgb() -> Self {
+        Self {
+            cgb: true,
+            ..Default::default()
+        }
+    }
+    pub fn is_powered(&self) -> bool {
+        self.powered
+    }
+    /// Advance the channels and the frame sequencer by `cycles` T-cycles.
+    pub fn tick(&mut self, cycles: u32) {
+        self.cycles += cycles as u64;
+        if self.powered {
+            self.tick_channels(cycles);
+        }
+        self.frame_sequencer_counter += cycles;
+        while self.frame_sequencer_counter >= FRAME_SEQUENCER_PERIOD {
+            self.frame_sequencer_counter -= FRAME_SEQUENCER_PERIOD;
+            if self.powered {
+                self.step_frame_sequencer();
+            }
+        }
+    }
+    fn tick_channels(&mut self, cycles: u32) {
+        self.square1.tick(cycles);
+        self.square2.tick(cycles);
+        if self.wave.enabled {
+            let period = self.wave.timer_period();
+            let steps = advance_timer(&mut self.wave.timer, period, cycles);
+            if steps > 0 {
+                self.wave.position = ((self.wave.position as u32 + steps) % 32) as u8;
+                let byte = self.wave_ram[self.wave.position as usize / 2];
+                self.wave.sample = if self.wave.position.is_multiple_of(2) {
+                    byte >> 4
+                } else {
+                    byte & 0x0F
+                };
+            }
+        }
+        self.noise.tick(cycles);
+    }
+    fn step_frame_sequencer(&mut self) {
+        if self.frame_sequencer_step.is_multiple_of(2) {
+            self.clock_lengths();
+        }
+        if self.frame_sequencer_step == 2 || self.frame_sequencer_step == 6 {
+            self.clock_sweep();
+        }
+        if self.frame_sequencer_step == 7 {
+            self.square1.envelope.clock();
+            self.square2.envelope.clock();
+            self.noise.envelope.clock();
+        }
+        self.frame_sequencer_step = (self.frame_sequencer_step + 1) % 8;
+    }
+    fn clock_lengths(&mut self) {
+        for channel in Channel::ALL {
+            if self.length_mut(channel).clock() {
+                self.disable(channel);
+            }
+        }
+    }
+    fn clock_sweep(&mut self) {
+        self.sweep.timer = self.sweep.timer.saturating_sub(1);
+        if self.sweep.timer != 0 {
+            return;
+        }
+        self.sweep.reload_timer();
+        if !self.sweep.enabled || self.sweep.pace == 0 {
+            return;
+        }
+        let period = self.sweep.next_period();
+        if period > 0x7FF {
+            self.square1.enabled = false;
+        } else if self.sweep.step != 0 {
+            self.sweep.shadow = period;
+            self.square1.period = period;
+            // The new period is checked again for overflow right away.
+            if self.sweep.next_period() > 0x7FF {
+                self.square1.enabled = false;
+            }
+        }
+    }
+    pub fn read(&self, address: u16) -> u8 {
+        match address {
+            NR52 => {
+                let mut value = 0x70;
+                if self.powered {
+                    value |= 0x80;
+                }
+                for (bit, channel) in Channel::ALL.into_iter().enumerate() {
+                    if self.channel_enabled(channel) {
+                        value |= 1 << bit;
+                    }
+                }
+                value
+            }
+            NR10..NR52 => {
+                let index = (address - NR10) as usize;
+                self.registers[index] | READ_MASKS[index]
+            }
+            WAVE_RAM_START..=WAVE_RAM_END => match self.wave_ram_index(address) {
+                Some(index) => self.wave_ram[index],
+                None => 0xFF,
+            },
+            PCM12 if self.cgb => self.square1.amplitude() | (self.square2.amplitude() << 4),
+            PCM34 if self.cgb => self.wave.amplitude() | (self.noise.amplitude() << 4),
+            _ => 0xFF,
+        }
+    }
+    /// Wave RAM byte reached through `address`.
+    ///
+    /// While channel 3 plays, the CPU sees the byte being played instead,
+    /// and on DMG only if the access happens as the channel reads it.
+    fn wave_ram_index(&self, address: u16) -> Option<usize> {
+        if !self.wave.enabled {
+            Some((address - WAVE_RAM_START) as usize)
+        } else if self.cgb || self.wave.just_read() {
+            Some(self.wave.position as usize / 2)
+        } else {
+            None
+        }
+    }
+    pub fn write(&mut self, address: u16, value: u8) {
+        if let (Some(vgm), NR10..=WAVE_RAM_END) = (&mut self.vgm, address) {
+            vgm.write(self.cycles, address, value);
+        }
+        if let WAVE_RAM_START..=WAVE_RAM_END = address {
+            if let Some(index) = self.wave_ram_index(address) {
+                self.wave_ram[index] = value;
+            }
+            return;
+        }
+        if address == NR52 {
+            self.set_power(value & 0x80 != 0);
+            return;
+        }
+        if !(NR10..NR52).contains(&address) {
+            return;
+        }
+        if !self.powered {
+            // The DMG keeps the length counters writable while powered off.
+            if !self.cgb {
+                match address {
+                    NR11 => self.square1.length.load((value & 0x3F) as u16),
+                    NR21 => self.square2.length.load((value & 0x3F) as u16),
+                    NR31 => self.wave.length.load(value as u16),
+                    NR41 => self.noise.length.load((value & 0x3F) as u16),
+                    _ => {}
+                }
+            }
+            return;
+        }
+        self.write_register(address, value);
+    }
+    fn write_register(&mut self, address: u16, value: u8) {
+        self.registers[(address - NR10) as usize] = value;
+        match address {
+            NR10 => {
+                let left_negate = self.sweep.write(value);
+                self.square1.enabled &= !left_negate;
+            }
+            NR11 => {
+                self.square1.duty = value >> 6;
+                self.square1.length.load((value & 0x3F) as u16);
+            }
+            NR12 => {
+                if self.square1.enabled {
+                    self.square1.envelope.zombie_write(value);
+                } else {
+                    self.square1.envelope.write(value);
+                }
+                if !self.square1.envelope.dac_enabled() {
+                    self.square1.enabled = false;
+                }
+            }
+            NR13 => self.square1.period = (self.square1.period & 0x700) | value as u16,
+            NR14 => {
+                self.square1.period = (self.square1.period & 0xFF) | ((value as u16 & 0x07) << 8);
+                self.write_control(Channel::Square1, value);
+            }
+            NR21 => {
+                self.square2.duty = value >> 6;
+                self.square2.length.load((value & 0x3F) as u16);
+            }
+            NR22 => {
+                if self.square2.enabled {
+                    self.square2.envelope.zombie_write(value);
+                } else {
+                    self.square2.envelope.write(value);
+                }
+                if !self.square2.envelope.dac_enabled() {
+                    self.square2.enabled = false;
+                }
+            }
+            NR23 => self.square2.period = (self.square2.period & 0x700) | value as u16,
+            NR24 => {
+                self.square2.period = (self.square2.period & 0xFF) | ((value as u16 & 0x07) << 8);
+                self.write_control(Channel::Square2, value);
+            }
+            NR30 => {
+                self.wave.dac = value & 0x80 != 0;
+                if !self.wave.dac {
+                    self.wave.enabled = false;
+                }
+            }
+            NR31 => self.wave.length.load(value as u16),
+            NR32 => self.wave.output_level = (value >> 5) & 0x03,
+            NR33 => self.wave.period = (self.wave.period & 0x700) | value as u16,
+            NR34 => {
+                self.wave.period = (self.wave.period & 0xFF) | ((value as u16 & 0x07) << 8);
+                self.write_control(Channel::Wave, value);
+            }
+            NR41 => self.noise.length.load((value & 0x3F) as u16),
+            NR42 => {
+                if self.noise.enabled {
+                    self.noise.envelope.zombie_write(value);
+                } else {
+                    self.noise.envelope.write(value);
+                }
+                if !self.noise.envelope.dac_enabled() {
+                    self.noise.enabled = false;
+                }
+            }
+            NR43 => self.noise.polynomial = value,
+            NR44 => self.write_control(Channel::Noise, value),
+            _ => {}
+        }
+    }
+    /// Handle the length enable and trigger bits of NRx4.
+    fn write_control(&mut self, channel: Channel, value: u8) {
+        let trigger = value & 0x80 != 0;
+        // When the next frame sequencer step does not clock the length
+        // counters, enabling them clocks them once more.
+        let extra_clock = !self.frame_sequencer_step.is_multiple_of(2);
+        let length = self.length_mut(channel);
+        let was_enabled = length.enabled;
+        length.enabled = value & 0x40 != 0;
+        if extra_clock && !was_enabled && length.enabled && length.clock() && !trigger {
+            self.disable(channel);
+        }
+        if trigger {
+            let length = self.length_mut(channel);
+            if length.counter == 0 {
+                length.counter = length.max;
+                if extra_clock && length.enabled {
+                    length.counter -= 1;
+                }
+            }
+            self.trigger(channel);
+        }
+    }
+    /// Start logging the register writes to a VGM recording.
+    ///
+    /// The current register state is written first, so that playback starts
+    /// from the same state without retriggering the channels.
+    pub fn start_vgm(&mut self) {
+        self.vgm = Some(VgmRecorder::new(self.cycles));
+        self.record_registers();
+    }
+    /// Write the current register state to the VGM recording.
+    fn record_registers(&mut self) {
+        let Some(vgm) = &mut self.vgm else {
+            return;
+        };
+        vgm.write(self.cycles, NR52, if self.powered { 0x80 } else { 0x00 });
+        for (address, value) in (NR10..NR52).zip(self.registers) {
+            let value = match address {
+                NR14 | NR24 | NR34 | NR44 => value & 0x7F,
+                _ => value,
+            };
+            vgm.write(self.cycles, address, value);
+        }
+        for (address, value) in (WAVE_RAM_START..=WAVE_RAM_END).zip(self.wave_ram) {
+            vgm.write(self.cycles, address, value);
+        }
+    }
+    /// Carry on the VGM recording, made until cycle `recorded_until`, once
+    /// a state is loaded: the registers it holds are written out anew.
+    pub fn resume_vgm(&mut self, recorded_until: u64) {
+        if let Some(vgm) = &mut self.vgm {
+            vgm.rebase(recorded_until, self.cycles);
+        }
+        self.record_registers();
+    }
+    /// T-cycles since power on.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+    pub fn is_recording_vgm(&self) -> bool {
+        self.vgm.is_some()
+    }
+    /// Mark the current position as the loop point of the VGM recording.
+    pub fn mark_vgm_loop(&mut self) {
+        if let Some(vgm) = &mut self.vgm {
+            vgm.mark_loop(self.cycles);
+        }
+    }
+    /// Stop the VGM recording and return the file contents.
+    pub fn stop_vgm(&mut self) -> Option<Vec<u8>> {
+        self.vgm.take().map(|vgm| vgm.finish(self.cycles))
+    }
+    fn set_power(&mut self, on: bool) {
+        if on && !self.powered {
+            self.frame_sequencer_step = 0;
+            self.square1.position = 0;
+            self.square2.position = 0;
+            self.wave.sample = 0;
+        }
+        if !on && self.powered {
+            // Powering off clears every register but wave RAM. The DMG keeps
+            // the length counters, the CGB clears them too.
+            let lengths = Channel::ALL.map(|channel| self.length_mut(channel).counter);
+            for address in NR10..NR52 {
+                self.write_register(address, 0);
+            }
+            for (channel, counter) in Channel::ALL.into_iter().zip(lengths) {
+                self.disable(channel);
+                self.length_mut(channel).counter = if self.cgb { 0 } else { counter };
+            }
+        }
+        self.powered = on;
+    }
+    fn length_mut(&mut self, channel: Channel) -> &mut LengthCounter {
+        match channel {
+            Channel::Square1 => &mut self.square1.length,
+            Channel::Square2 => &mut self.square2.length,
+            Channel::Wave => &mut self.wave.length,
+            Channel::Noise => &mut self.noise.length,
+        }
+    }
+    fn disable(&mut self, channel: Channel) {
+        match channel {
+            Channel::Square1 => self.square1.enabled = false,
+            Channel::Square2 => self.square2.enabled = false,
+            Channel::Wave => self.wave.enabled = false,
+            Channel::Noise => self.noise.enabled = false,
+        }
+    }
+    fn trigger(&mut self, channel: Channel) {
+        match channel {
+            Channel::Square1 => {
+                let square = &mut self.square1;
+                square.enabled = square.envelope.dac_enabled();
+                square.envelope.trigger();
+                square.timer = square.timer_period();
+                self.sweep.shadow = square.period;
+                self.sweep.negated = false;
+                self.sweep.reload_timer();
+                self.sweep.enabled = self.sweep.pace != 0 || self.sweep.step != 0;
+                if self.sweep.step != 0 && self.sweep.next_period() > 0x7FF {
+                    square.enabled = false;
+                }
+            }
+            Channel::Square2 => {
+                let square = &mut self.square2;
+                square.enabled = square.envelope.dac_enabled();
+                square.envelope.trigger();
+                square.timer = square.timer_period();
+            }
+            Channel::Wave => {
+                // On DMG retriggering while the channel is about to read a
+                // sample corrupts the first bytes of wave RAM.
+                if !self.cgb && self.wave.enabled && self.wave.timer == 2 {
+                    let index = ((self.wave.position as usize + 1) % 32) / 2;
+                    if index < 4 {
+                        self.wave_ram[0] = self.wave_ram[index];
+                    } else {
+                        let block = index & !0x03;
+                        self.wave_ram.copy_within(block..block + 4, 0);
+                    }
+                }
+                self.wave.enabled = self.wave.dac;
+                self.wave.position = 0;
+                // The first sample is read after a 6 cycles delay.
+                self.wave.timer = self.wave.timer_period() + 6;
+            }
+            Channel::Noise => {
+                let noise = &mut self.noise;
+                noise.enabled = noise.envelope.dac_enabled();
+                noise.envelope.trigger();
+                noise.timer = noise.timer_period();
+                noise.lfsr = 0x7FFF;
+            }
+        }
+    }
+    fn channel_enabled(&self, channel: Channel) -> bool {
+        match channel {
+            Channel::Square1 => self.square1.enabled,
+            Channel::Square2 => self.square2.enabled,
+            Channel::Wave => self.wave.enabled,
+            Channel::Noise => self.noise.enabled,
+        }
+    }
+    /// Current state of `channel` as it would be heard.
+    pub fn channel(&self, channel: Channel) -> ChannelState {
+        let active = self.channel_enabled(channel);
+        match channel {
+            Channel::Square1 | Channel::Square2 => {
+                let square = if channel == Channel::Square1 {
+                    &self.square1
+                } else {
+                    &self.square2
+                };
+                ChannelState {
+                    active,
+                    period: square.period,
+                    frequency: 131_072. / (2048 - square.period) as f32,
+                    volume: square.envelope.volume,
+                    duty: Some(square.duty),
+                }
+            }
+            Channel::Wave => ChannelState {
+                active,
+                period: self.wave.period,
+                frequency: 65_536. / (2048 - self.wave.period) as f32,
+                // Output level 1 is full volume, 2 and 3 shift the samples right.
+                volume: match self.wave.output_level {
+                    0 => 0,
+                    level => 15 >> (level - 1),
+                },
+                duty: None,
+            },
+            Channel::Noise => {
+                let polynomial = self.noise.polynomial;
+                let divisor = match polynomial & 0x07 {
+                    0 => 0.5,
+                    divisor => divisor as f32,
+                };
+                let shift = (polynomial >> 4) as i32;
+                ChannelState {
+                    active,
+                    period: polynomial as u16,
+                    frequency: 262_144. / (divisor * 2f32.powi(shift)),
+                    volume: self.noise.envelope.volume,
+                    duty: None,
+                }
+            }
+        }
+    }
+}
+
+impl Snapshot for LengthCounter {
+    fn save(&self, writer: &mut StateWriter) {
+        writer.bool(self.enabled);
+        writer.u16(self.counter);
+    }
+    fn load(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.enabled = reader.bool()?;
+        self.counter = reader.u16()?;
+        Ok(())
+    }
+}
+impl Snapshot for Envelope {
+    fn save(&self, writer: &mut StateWriter) {
+        writer.u8(self.initial);
+        writer.bool(self.increase);
+        writer.u8(self.pace);
+        writer.u8(self.volume);
+        writer.u8(self.timer);
+        writer.bool(self.running);
+    }
+    fn load(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.initial = reader.u8()?;
+        self.increase = reader.bool()?;
+        self.pace = reader.u8()?;
+        self.volume = reader.u8()?;
+        self.timer = reader.u8()?;
+        self.running = reader.bool()?;
+        Ok(())
+    }
+}
+impl Snapshot for Sweep {
+    fn save(&self, writer: &mut StateWriter) {
+        writer.u8(self.pace);
+        writer.bool(self.negate);
+        writer.u8(self.step);
+        writer.u8(self.timer);
+        writer.bool(self.enabled);
+        writer.u16(self.shadow);
+        writer.bool(self.negated);
+    }
+    fn load(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.pace = reader.u8()?;
+        self.negate = reader.bool()?;
+        self.step = reader.u8()?;
+        self.timer = reader.u8()?;
+        self.enabled = reader.bool()?;
+        self.shadow = reader.u16()?;
+        self.negated = reader.bool()?;
+        Ok(())
+    }
+}
+impl Snapshot for SquareChannel {
+    fn save(&self, writer: &mut StateWriter) {
+        writer.bool(self.enabled);
+        writer.u8(self.duty);
+        writer.u16(self.period);
+        self.length.save(writer);
+        self.envelope.save(writer);
+        writer.u32(self.timer);
+        writer.u8(self.position);
+    }
+    fn load(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.enabled = reader.bool()?;
+        self.duty = reader.u8()? & 3;
+        self.period = reader.u16()? & 0x7FF;
+        Snapshot::load(&mut self.length, reader)?;
+        self.envelope.load(reader)?;
+        self.timer = reader.u32()?;
+        self.position = reader.u8()? % 8;
+        Ok(())
+    }
+}
+impl Snapshot for WaveChannel {
+    fn save(&self, writer: &mut StateWriter) {
+        writer.bool(self.enabled);
+        writer.bool(self.dac);
+        writer.u8(self.output_level);
+        writer.u16(self.period);
+        self.length.save(writer);
+        writer.u32(self.timer);
+        writer.u8(self.position);
+        writer.u8(self.sample);
+    }
+    fn load(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.enabled = reader.bool()?;
+        self.dac = reader.bool()?;
+        self.output_level = reader.u8()? & 3;
+        self.period = reader.u16()? & 0x7FF;
+        Snapshot::load(&mut self.length, reader)?;
+        self.timer = reader.u32()?;
+        self.position = reader.u8()? % 32;
+        self.sample = reader.u8()?;
+        Ok(())
+    }
+}
+impl Snapshot for NoiseChannel {
+    fn save(&self, writer: &mut StateWriter) {
+        writer.bool(self.enabled);
+        writer.u8(self.polynomial);
+        self.length.save(writer);
+        self.envelope.save(writer);
+        writer.u32(self.timer);
+        writer.u16(self.lfsr);
+    }
+    fn load(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.enabled = reader.bool()?;
+        self.polynomial = reader.u8()?;
+        Snapshot::load(&mut self.length, reader)?;
+        self.envelope.load(reader)?;
+        self.timer = reader.u32()?;
+        self.lfsr = reader.u16()?;
+        Ok(())
+    }
+}
+/// Everything but the VGM recording, which belongs to the session: it
+/// goes on from the loaded registers.
+impl Snapshot for Apu {
+    fn save(&self, writer: &mut StateWriter) {
+        writer.bool(self.powered);
+        self.square1.save(writer);
+        self.sweep.save(writer);
+        self.square2.save(writer);
+        self.wave.save(writer);
+        self.noise.save(writer);
+        writer.bytes(&self.wave_ram);
+        writer.bytes(&self.registers);
+        writer.u8(self.frame_sequencer_step);
+        writer.u32(self.frame_sequencer_counter);
+        writer.u64(self.cycles);
+    }
+    fn load(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.powered = reader.bool()?;
+        self.square1.load(reader)?;
+        self.sweep.load(reader)?;
+        self.square2.load(reader)?;
+        self.wave.load(reader)?;
+        self.noise.load(reader)?;
+        reader.bytes_into(&mut self.wave_ram)?;
+        reader.bytes_into(&mut self.registers)?;
+        self.frame_sequencer_step = reader.u8()? % 8;
+        self.frame_sequencer_counter = reader.u32()? % FRAME_SEQUENCER_PERIOD;
+        self.cycles = reader.u64()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn powered_apu() -> Apu {
+        let mut apu = Apu::new();
+        apu.write(NR52, 0x80);
+        apu
+    }
+
+    #[test]
+    fn trigger_square_channel() {
+        let mut apu = powered_apu();
+        apu.write(NR21, 0x80);
+        apu.write(NR22, 0xF0);
+        // 1750 -> 131072 / 298 ~= 440 Hz
+        apu.write(NR23, 0xD6);
+        apu.write(NR24, 0x86);
+        let state = apu.channel(Channel::Square2);
+        assert!(state.active);
+        assert_eq!(state.period, 0x6D6);
+        assert_eq!(state.volume, 15);
+        assert_eq!(state.duty, Some(2));
+        assert!((state.frequency - 439.8).abs() < 0.1);
+        assert_eq!(apu.read(NR52), 0xF2);
+    }
+
+    #[test]
+    fn length_counter_disables_channel() {
+        let mut apu = powered_apu();
+        apu.write(NR12, 0xF0);
+        apu.write(NR11, 0x3E);
+        apu.write(NR14, 0xC0);
+        assert!(apu.channel(Channel::Square1).active);
+        // Two length clocks happen in the first 16384 cycles.
+        apu.tick(FRAME_SEQUENCER_PERIOD * 3);
+        assert!(!apu.channel(Channel::Square1).active);
+    }
+
+    #[test]
+    fn writes_ignored_while_powered_off() {
+        let mut apu = Apu::new();
+        apu.write(NR50, 0x77);
+        assert_eq!(apu.read(NR50), 0x00);
+        apu.write(WAVE_RAM_START, 0x12);
+        assert_eq!(apu.read(WAVE_RAM_START), 0x12);
+    }
+
+    #[test]
+    fn power_off_clears_registers() {
+        let mut apu = powered_apu();
+        apu.write(NR50, 0x77);
+        apu.write(NR52, 0x00);
+        assert_eq!(apu.read(NR50), 0x00);
+        // Only the DMG lets the length counters be written while off.
+        apu.write(NR41, 0x3F);
+        assert_eq!(apu.noise.length.counter, 1);
+        let mut apu = Apu::new_cgb();
+        apu.write(NR41, 0x3F);
+        assert_eq!(apu.noise.length.counter, 0);
+    }
+
+    #[test]
+    fn enabling_length_clocks_it_once_more() {
+        let mut apu = powered_apu();
+        apu.write(NR12, 0xF0);
+        apu.write(NR11, 0x3F);
+        apu.write(NR14, 0x80);
+        // Step 0 ran, the next one does not clock the lengths.
+        apu.tick(FRAME_SEQUENCER_PERIOD);
+        apu.write(NR14, 0x40);
+        assert!(!apu.channel(Channel::Square1).active);
+    }
+
+    #[test]
+    fn zombie_mode_volume() {
+        let mut apu = powered_apu();
+        apu.write(NR22, 0x50);
+        apu.write(NR24, 0x80);
+        apu.write(NR22, 0x58);
+        // A stopped envelope adds 1, switching to increase sets 16 - volume.
+        assert_eq!(apu.channel(Channel::Square2).volume, 10);
+    }
+
+    #[test]
+    fn pcm_registers_are_cgb_only() {
+        let mut apu = Apu::new_cgb();
+        apu.write(NR52, 0x80);
+        apu.write(NR42, 0xA0);
+        apu.write(NR44, 0x80);
+        assert_eq!(apu.read(PCM34), 0x00);
+        // The LFSR output goes high after 15 clocks of 8 cycles.
+        apu.tick(8 * 15);
+        assert_eq!(apu.read(PCM34), 0xA0);
+        assert_eq!(powered_apu().read(PCM34), 0xFF);
+    }
+
+    #[test]
+    fn load_earlier_state_while_recording_vgm() {
+        let mut apu = powered_apu();
+        let mut writer = StateWriter::default();
+        apu.save(&mut writer);
+        let state = writer.into_bytes();
+        apu.tick(CPU_CLOCK);
+        apu.start_vgm();
+        apu.tick(CPU_CLOCK);
+        // Back to before the recording started.
+        let recorded_until = apu.cycles();
+        apu.load(&mut StateReader::new(&state)).unwrap();
+        apu.resume_vgm(recorded_until);
+        apu.tick(CPU_CLOCK / 2);
+        let vgm = apu.stop_vgm().unwrap();
+        // The recording goes on: one and a half seconds at 44100 Hz.
+        let samples = u32::from_le_bytes(vgm[0x18..0x1C].try_into().unwrap());
+        assert_eq!(samples, 66_150);
+    }
+}
@@ -0,0 +1,283 @@
+//! Boot ROMs, mapped over the start of the cartridge until FF50 is written.
+//!
+//! The boot ROMs of the DMG, MGB and SGB are 256 bytes long and cover
+//! 0x0000-0x00FF. Those of the CGB and AGB are 2304 bytes long and also
+//! cover 0x0200-0x08FF, leaving the cartridge header visible in between.
+//! Without a boot ROM the console starts in the state the boot ROM leaves,
+//! and `LogoAnimation` shows the logo scrolling down as the boot ROM would.
+
+use std::fmt::Display;
+
+use crate::{
+    model::Model,
+    sgb::{SCREEN_HEIGHT, SCREEN_WIDTH},
+    state::{Snapshot, StateError, StateReader, StateWriter},
+};
+
+/// Writing a non-zero value unmaps the boot ROM until the next reset.
+pub const BOOT_OFF: u16 = 0xFF50;
+
+const DMG_SIZE: usize = 0x100;
+const CGB_SIZE: usize = 0x900;
+/// Cartridge header, seen through the CGB boot ROM.
+const HEADER: std::ops::Range<u16> = 0x100..0x200;
+
+/// SCY when the logo starts scrolling, which hides it above the screen.
+const LOGO_START_SCROLL: u8 = 0x64;
+/// Frames the logo stays still once in place, while the chime plays.
+const LOGO_HOLD_FRAMES: u32 = 60;
+/// Position of the logo in the background, in pixels.
+const LOGO_X: usize = 32;
+const LOGO_Y: usize = 64;
+/// The ® drawn right of the logo, one byte per row.
+const REGISTERED: [u8; 8] = [0x3C, 0x42, 0xB9, 0xA5, 0xB9, 0xA5, 0x42, 0x3C];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BootRomError {
+    BadSize(usize),
+}
+impl Display for BootRomError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BootRomError::BadSize(size) => write!(
+                f,
+                "a boot ROM is {DMG_SIZE} or {CGB_SIZE} bytes long, not {size}"
+            ),
+        }
+    }
+}
+impl std::error::Error for BootRomError {}
+
+/// CPU registers, as the boot ROM leaves them or as they are at power on.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Registers {
+    pub a: u8,
+    pub f: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub h: u8,
+    pub l: u8,
+    pub sp: u16,
+    pub pc: u16,
+    /// The timer divider, which the boot ROM leaves at a value of its own.
+    pub div: u8,
+}
+impl Registers {
+    /// The registers once the boot ROM of `model` jumps to the cartridge
+    /// at 0x0100.
+    pub fn after_boot(model: Model) -> Self {
+        let (a, f, b, c, d, e, h, l) = match model {
+            Model::Dmg0 => (0x01, 0x00, 0xFF, 0x13, 0x00, 0xC1, 0x84, 0x03),
+            Model::Dmg => (0x01, 0xB0, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D),
+            Model::Mgb => (0xFF, 0xB0, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D),
+            Model::Sgb => (0x01, 0x00, 0x00, 0x14, 0x00, 0x00, 0xC0, 0x60),
+            Model::Sgb2 => (0xFF, 0x00, 0x00, 0x14, 0x00, 0x00, 0xC0, 0x60),
+            Model::Cgb0 | Model::Cgb => (0x11, 0x80, 0x00, 0x00, 0xFF, 0x56, 0x00, 0x0D),
+            Model::Agb => (0x11, 0x00, 0x01, 0x00, 0xFF, 0x56, 0x00, 0x0D),
+        };
+        Self {
+            a,
+            f,
+            b,
+            c,
+            d,
+            e,
+            h,
+            l,
+            sp: 0xFFFE,
+            pc: 0x0100,
+            div: model.div(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BootRom {
+    bytes: Vec<u8>,
+    mapped: bool,
+}
+impl BootRom {
+    pub fn parse(bytes: &[u8]) -> Result<Self, BootRomError> {
+        if bytes.len() != DMG_SIZE && bytes.len() != CGB_SIZE {
+            return Err(BootRomError::BadSize(bytes.len()));
+        }
+        Ok(Self {
+            bytes: bytes.to_vec(),
+            mapped: true,
+        })
+    }
+    /// Whether this is the boot ROM of a CGB or an AGB.
+    pub fn is_cgb(&self) -> bool {
+        self.bytes.len() == CGB_SIZE
+    }
+    pub fn is_mapped(&self) -> bool {
+        self.mapped
+    }
+    /// Map the boot ROM again, as on reset.
+    pub fn reset(&mut self) {
+        self.mapped = true;
+    }
+    /// The byte of the boot ROM at `address`, `None` where the cartridge
+    /// shows through.
+    pub fn read(&self, address: u16) -> Option<u8> {
+        if !self.mapped || HEADER.contains(&address) {
+            return None;
+        }
+        self.bytes.get(address as usize).copied()
+    }
+    pub fn write(&mut self, address: u16, value: u8) {
+        if address == BOOT_OFF && value != 0 {
+            self.mapped = false;
+        }
+    }
+}
+
+/// The logo of the cartridge header scrolling down from the top of the
+/// screen, then staying still for a second, as the DMG boot ROM shows it.
+#[derive(Debug, Clone)]
+pub struct LogoAnimation {
+    logo: [u8; 48],
+    scroll: u8,
+    hold: u32,
+}
+impl LogoAnimation {
+    pub fn new(logo: &[u8; 48]) -> Self {
+        Self {
+            logo: *logo,
+            scroll: LOGO_START_SCROLL,
+            hold: LOGO_HOLD_FRAMES,
+        }
+    }
+    /// Advance by a frame.
+    pub fn tick(&mut self) {
+        if self.scroll > 0 {
+            self.scroll -= 1;
+        } else {
+            self.hold = self.hold.saturating_sub(1);
+        }
+    }
+    pub fn is_done(&self) -> bool {
+        self.scroll == 0 && self.hold == 0
+    }
+    /// Whether the pixel of the 48 x 8 logo at `x`, `y` is set. Each byte
+    /// holds two rows of four pixels, the top half comes first.
+    fn logo_pixel(&self, x: usize, y: usize) -> bool {
+        let byte = self.logo[y / 4 * 24 + x / 4 * 2 + y % 4 / 2];
+        let nibble = if y.is_multiple_of(2) {
+            byte >> 4
+        } else {
+            byte & 0x0F
+        };
+        nibble >> (3 - x % 4) & 1 != 0
+    }
+    /// Color index of each pixel of the screen, 0 for white to 3 for black.
+    pub fn frame(&self) -> Vec<u8> {
+        let mut pixels = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT];
+        let mut plot = |x: usize, y: usize| {
+            let y = (y + 256 - self.scroll as usize) % 256;
+            if y < SCREEN_HEIGHT {
+                pixels[y * SCREEN_WIDTH + x] = 3;
+            }
+        };
+        // The boot ROM doubles the logo in both directions.
+        for y in 0..16 {
+            for x in 0..96 {
+                if self.logo_pixel(x / 2, y / 2) {
+                    plot(LOGO_X + x, LOGO_Y + y);
+                }
+            }
+        }
+        for (y, row) in REGISTERED.iter().enumerate() {
+            for x in 0..8 {
+                if row << x & 0x80 != 0 {
+                    plot(LOGO_X + 96 + x, LOGO_Y + y);
+                }
+            }
+        }
+        pixels
+    }
+}
+
+impl Snapshot for Registers {
+    fn save(&self, writer: &mut StateWriter) {
+        for register in [
+            self.a, self.f, self.b, self.c, self.d, self.e, self.h, self.l,
+        ] {
+            writer.u8(register);
+        }
+        writer.u16(self.sp);
+        writer.u16(self.pc);
+        writer.u8(self.div);
+    }
+    fn load(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        for register in [
+            &mut self.a,
+            &mut self.f,
+            &mut self.b,
+            &mut self.c,
+            &mut self.d,
+            &mut self.e,
+            &mut self.h,
+            &mut self.l,
+        ] {
+            *register = reader.u8()?;
+        }
+        self.sp = reader.u16()?;
+        self.pc = reader.u16()?;
+        self.div = reader.u8()?;
+        Ok(())
+    }
+}
+/// Whether the boot ROM is still mapped, its bytes come from the user.
+impl Snapshot for BootRom {
+    fn save(&self, writer: &mut StateWriter) {
+        writer.bool(self.mapped);
+    }
+    fn load(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.mapped = reader.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unmap_on_boot_off() {
+        assert_eq!(
+            BootRom::parse(&[0; 512]).unwrap_err(),
+            BootRomError::BadSize(512)
+        );
+        let mut bytes = vec![0x31; CGB_SIZE];
+        bytes[0x8FF] = 0xE0;
+        let mut boot_rom = BootRom::parse(&bytes).unwrap();
+        assert!(boot_rom.is_cgb());
+        assert_eq!(boot_rom.read(0x0000), Some(0x31));
+        assert_eq!(boot_rom.read(0x0104), None);
+        assert_eq!(boot_rom.read(0x08FF), Some(0xE0));
+        assert_eq!(boot_rom.read(0x0900), None);
+        boot_rom.write(BOOT_OFF, 0);
+        assert!(boot_rom.is_mapped());
+        boot_rom.write(BOOT_OFF, 0x11);
+        assert_eq!(boot_rom.read(0x0000), None);
+    }
+
+    #[test]
+    fn scroll_logo_into_place() {
+        let mut animation = LogoAnimation::new(&crate::cartridge::NINTENDO_LOGO);
+        assert!(animation.frame().iter().all(|&shade| shade == 0));
+        while !animation.is_done() {
+            animation.tick();
+        }
+        // The top left pixel of the N, doubled.
+        let frame = animation.frame();
+        let pixel = |x: usize, y: usize| frame[y * SCREEN_WIDTH + x];
+        assert_eq!(pixel(LOGO_X, LOGO_Y), 3);
+        assert_eq!(pixel(LOGO_X + 1, LOGO_Y + 1), 3);
+        assert_eq!(pixel(LOGO_X + 4, LOGO_Y), 0);
+        assert_eq!(pixel(LOGO_X + 96 + 2, LOGO_Y), 3);
+    }
+}
@@ -0,0 +1,253 @@
+//! Cartridges and their header, at 0x0100-0x014F of the ROM.
+//!
+//! Cartridges with a battery keep their external RAM, and the clock of an
+//! MBC3, while the console is off: saves hold the RAM followed by the clock.
+
+use std::fmt::Display;
+
+use crate::{
+    model::Model,
+    rtc::{Rtc, FOOTER_SIZE},
+    state::{crc32, Snapshot, StateError, StateReader, StateWriter},
+};
+
+/// The logo every licensed cartridge carries, which the boot ROM checks
+/// before starting it.
+pub const NINTENDO_LOGO: [u8; 48] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+    0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+    0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+
+const LOGO: std::ops::Range<usize> = 0x104..0x134;
+const TITLE: std::ops::Range<usize> = 0x134..0x144;
+const CARTRIDGE_TYPE: usize = 0x147;
+const RAM_SIZE: usize = 0x149;
+const HEADER_END: usize = 0x150;
+/// The MBC2 has 512 half-bytes of RAM built in.
+const MBC2_RAM_SIZE: usize = 512;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CartridgeError {
+    TooShort(usize),
+    /// A save does not fit the RAM of the cartridge.
+    BadSave(usize),
+}
+impl Display for CartridgeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CartridgeError::TooShort(len) => {
+                write!(f, "file too short for a cartridge header ({len} bytes)")
+            }
+            CartridgeError::BadSave(len) => {
+                write!(f, "save does not match the cartridge RAM ({len} bytes)")
+            }
+        }
+    }
+}
+impl std::error::Error for CartridgeError {}
+
+#[derive(Debug, Clone)]
+pub struct Cartridge {
+    rom: Vec<u8>,
+    /// CRC-32 of the ROM, which save states are checked against.
+    crc: u32,
+    ram: Vec<u8>,
+    battery: bool,
+    rtc: Option<Rtc>,
+    /// Whether the RAM or the clock changed since the last save.
+    dirty: bool,
+}
+impl Cartridge {
+    pub fn parse(bytes: &[u8]) -> Result<Self, CartridgeError> {
+        if bytes.len() < HEADER_END {
+            return Err(CartridgeError::TooShort(bytes.len()));
+        }
+        let kind = bytes[CARTRIDGE_TYPE];
+        let battery = matches!(
+            kind,
+            0x03 | 0x06 | 0x09 | 0x0D | 0x0F | 0x10 | 0x13 | 0x1B | 0x1E | 0x22 | 0xFF
+        );
+        let ram_size = match (kind, bytes[RAM_SIZE]) {
+            (0x05 | 0x06, _) => MBC2_RAM_SIZE,
+            (_, 0x01) => 0x800,
+            (_, 0x02) => 0x2000,
+            (_, 0x03) => 0x8000,
+            (_, 0x04) => 0x20000,
+            (_, 0x05) => 0x10000,
+            _ => 0,
+        };
+        Ok(Self {
+            rom: bytes.to_vec(),
+            crc: crc32(bytes),
+            ram: vec![0; ram_size],
+            battery,
+            rtc: matches!(kind, 0x0F | 0x10).then(Rtc::new),
+            dirty: false,
+        })
+    }
+    pub fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+    pub fn crc(&self) -> u32 {
+        self.crc
+    }
+    /// The title, up to the first NUL. CGB cartridges use the last bytes
+    /// for their manufacturer code and flags, which are left out.
+    pub fn title(&self) -> String {
+        let title = &self.rom[TITLE];
+        let end = title
+            .iter()
+            .position(|&byte| byte == 0 || byte >= 0x80)
+            .unwrap_or(title.len());
+        String::from_utf8_lossy(&title[..end]).trim().to_string()
+    }
+    pub fn logo(&self) -> &[u8; 48] {
+        self.rom[LOGO]
+            .try_into()
+            .expect("the header is checked on parse")
+    }
+    /// Whether the logo is the one the boot ROM expects, real hardware
+    /// locks up otherwise.
+    pub fn has_valid_logo(&self) -> bool {
+        *self.logo() == NINTENDO_LOGO
+    }
+    /// The model the header asks for.
+    pub fn model(&self) -> Model {
+        Model::from_header(&self.rom)
+    }
+    /// Whether the RAM, and the clock if any, are kept by a battery.
+    pub fn has_battery(&self) -> bool {
+        self.battery
+    }
+    pub fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+    /// Write the external RAM, as the bus does for 0xA000-0xBFFF.
+    pub fn write_ram(&mut self, offset: usize, value: u8) {
+        if let Some(byte) = self.ram.get_mut(offset) {
+            *byte = value;
+            self.dirty = true;
+        }
+    }
+    pub fn rtc(&self) -> Option<&Rtc> {
+        self.rtc.as_ref()
+    }
+    /// Write a clock register, as the bus does for 0xA000-0xBFFF with one
+    /// of them mapped there.
+    pub fn write_rtc(&mut self, register: u8, value: u8) {
+        if let Some(rtc) = &mut self.rtc {
+            rtc.write(register, value);
+            self.dirty = true;
+        }
+    }
+    /// Advance the clock by `cycles` T-cycles at `clock` T-cycles per
+    /// second, that of the model.
+    pub fn tick(&mut self, cycles: u32, clock: u32) {
+        if let Some(rtc) = &mut self.rtc {
+            rtc.tick(cycles, clock);
+        }
+    }
+    /// Whether the RAM or the clock were written since the last call to
+    /// `battery_save`.
+    ///
+    /// Only `write_ram` and `write_rtc` set it. Nothing calls them until
+    /// jade_core has a bus, so for now only loading a state does, through
+    /// `mark_dirty`.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+    /// Note that the RAM no longer matches the save file, as after loading
+    /// a state.
+    pub fn mark_dirty(&mut self) {
+        self.dirty = true;
+    }
+    /// The RAM, followed by the clock saved at `now` in UNIX seconds.
+    pub fn battery_save(&mut self, now: u64) -> Vec<u8> {
+        self.dirty = false;
+        let mut save = self.ram.clone();
+        if let Some(rtc) = &self.rtc {
+            save.extend_from_slice(&rtc.footer(now));
+        }
+        save
+    }
+    /// Restore a save, the clock catches up with the time elapsed until
+    /// `now`. Saves without a clock keep the current one.
+    pub fn load_battery_save(&mut self, save: &[u8], now: u64) -> Result<(), CartridgeError> {
+        if save.len() < self.ram.len() || save.len() > self.ram.len() + FOOTER_SIZE {
+            return Err(CartridgeError::BadSave(save.len()));
+        }
+        let (ram, footer) = save.split_at(self.ram.len());
+        if self.rtc.is_some() && !footer.is_empty() {
+            self.rtc =
+                Some(Rtc::from_footer(footer, now).ok_or(CartridgeError::BadSave(save.len()))?);
+        } else if !footer.is_empty() {
+            return Err(CartridgeError::BadSave(save.len()));
+        }
+        self.ram.copy_from_slice(ram);
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+/// The RAM and the clock, the ROM is checked through the state header.
+impl Snapshot for Cartridge {
+    fn save(&self, writer: &mut StateWriter) {
+        writer.bytes(&self.ram);
+        if let Some(rtc) = &self.rtc {
+            rtc.save(writer);
+        }
+    }
+    fn load(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        reader.bytes_into(&mut self.ram)?;
+        if let Some(rtc) = &mut self.rtc {
+            rtc.load(reader)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_header() {
+        assert_eq!(
+            Cartridge::parse(&[0; 0x100]).unwrap_err(),
+            CartridgeError::TooShort(0x100)
+        );
+        let mut rom = vec![0; 0x8000];
+        rom[LOGO].copy_from_slice(&NINTENDO_LOGO);
+        rom[TITLE.start..TITLE.start + 6].copy_from_slice(b"TETRIS");
+        let cartridge = Cartridge::parse(&rom).unwrap();
+        assert!(cartridge.has_valid_logo());
+        assert_eq!(cartridge.title(), "TETRIS");
+        assert_eq!(cartridge.model(), Model::Dmg);
+    }
+
+    #[test]
+    fn save_with_clock() {
+        let mut rom = vec![0; 0x8000];
+        rom[CARTRIDGE_TYPE] = 0x10;
+        rom[RAM_SIZE] = 0x03;
+        let mut cartridge = Cartridge::parse(&rom).unwrap();
+        assert!(cartridge.has_battery());
+        cartridge.write_ram(0x7FFF, 0x42);
+        assert!(cartridge.is_dirty());
+        let save = cartridge.battery_save(100);
+        assert_eq!(save.len(), 0x8000 + FOOTER_SIZE);
+        assert!(!cartridge.is_dirty());
+
+        let mut loaded = Cartridge::parse(&rom).unwrap();
+        loaded.load_battery_save(&save, 160).unwrap();
+        assert_eq!(loaded.ram()[0x7FFF], 0x42);
+        let mut rtc = loaded.rtc().unwrap().clone();
+        rtc.latch();
+        assert_eq!(rtc.read(crate::rtc::SECONDS + 1), 1);
+        assert_eq!(
+            loaded.load_battery_save(&[0; 16], 0).unwrap_err(),
+            CartridgeError::BadSave(16)
+        );
+    }
+}
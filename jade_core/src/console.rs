@@ -0,0 +1,255 @@
+//! A Game Boy made of the components emulated so far, clocked together.
+
+use crate::{
+    apu::Apu,
+    boot::{BootRom, Registers},
+    cartridge::Cartridge,
+    four_player::FourPlayerAdapter,
+    infrared::Infrared,
+    joypad::Joypad,
+    model::Model,
+    serial::Serial,
+    sgb::Sgb,
+    state::{
+        crc32, read_snapshot, read_state, write_snapshot, write_state, Snapshot, StateError,
+        StateHeader,
+    },
+};
+
+/// T-cycles consoles clocked in lockstep run before the next one catches
+/// up: the time to shift a byte with the CGB fast clock.
+pub const LOCKSTEP_CYCLES: u32 = 128;
+
+#[derive(Debug)]
+pub struct Console {
+    pub model: Model,
+    /// Registers the CPU starts from.
+    pub registers: Registers,
+    pub boot_rom: Option<BootRom>,
+    pub cartridge: Option<Cartridge>,
+    pub apu: Apu,
+    pub joypad: Joypad,
+    pub serial: Serial,
+    pub infrared: Infrared,
+    /// The SNES side of a Super Game Boy.
+    pub sgb: Option<Sgb>,
+}
+impl Default for Console {
+    fn default() -> Self {
+        Self::with_model(Model::default())
+    }
+}
+impl Console {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// A console of `model` in the state its boot ROM leaves.
+    pub fn with_model(model: Model) -> Self {
+        let cgb = model.is_cgb();
+        Self {
+            model,
+            registers: Registers::after_boot(model),
+            boot_rom: None,
+            cartridge: None,
+            apu: if cgb { Apu::new_cgb() } else { Apu::new() },
+            joypad: Joypad::new(),
+            serial: if cgb {
+                Serial::new_cgb()
+            } else {
+                Serial::new()
+            },
+            infrared: if cgb {
+                Infrared::new_cgb()
+            } else {
+                Infrared::new()
+            },
+            sgb: model.is_sgb().then(Sgb::new),
+        }
+    }
+    /// Start from `boot_rom` at 0x0000 instead of the state it leaves.
+    pub fn load_boot_rom(&mut self, boot_rom: BootRom) {
+        self.registers = Registers::default();
+        self.boot_rom = Some(boot_rom);
+    }
+    /// CRC-32 of the ROM of the cartridge, that of no data without one.
+    pub fn rom_crc(&self) -> u32 {
+        self.cartridge
+            .as_ref()
+            .map_or_else(|| crc32(&[]), Cartridge::crc)
+    }
+    /// A save state of the console made at `now`, in UNIX seconds, showing
+    /// `thumbnail`. What is plugged in the ports and the SGB are left out.
+    pub fn save_state(&self, thumbnail: &[u8], now: u64) -> Vec<u8> {
+        let header = StateHeader::new(self.rom_crc(), now, thumbnail);
+        write_state(&header, &self.sections())
+    }
+    /// Restore a state made by `save_state` with the same cartridge.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<StateHeader, StateError> {
+        let rom_crc = self.rom_crc();
+        let recorded_until = self.apu.cycles();
+        let header = read_state(state, rom_crc, &mut self.sections_mut())?;
+        self.loaded(recorded_until);
+        Ok(header)
+    }
+    /// What `save_state` saves without the header, for rewinding.
+    pub fn save_snapshot(&self) -> Vec<u8> {
+        write_snapshot(&self.sections())
+    }
+    /// Restore a snapshot made by `save_snapshot`.
+    pub fn load_snapshot(&mut self, snapshot: &[u8]) -> Result<(), StateError> {
+        let recorded_until = self.apu.cycles();
+        read_snapshot(snapshot, &mut self.sections_mut())?;
+        self.loaded(recorded_until);
+        Ok(())
+    }
+    /// What follows a load once every section is in, the loads themselves
+    /// leave no trace should a later section be rejected.
+    fn loaded(&mut self, recorded_until: u64) {
+        self.apu.resume_vgm(recorded_until);
+        if let Some(cartridge) = &mut self.cartridge {
+            cartridge.mark_dirty();
+        }
+    }
+    fn sections(&self) -> Vec<(&[u8; 4], &dyn Snapshot)> {
+        let mut sections: Vec<(&[u8; 4], &dyn Snapshot)> = vec![
+            (b"REGS", &self.registers),
+            (b"APU ", &self.apu),
+            (b"JOYP", &self.joypad),
+            (b"SERL", &self.serial),
+            (b"INFR", &self.infrared),
+        ];
+        if let Some(boot_rom) = &self.boot_rom {
+            sections.push((b"BOOT", boot_rom));
+        }
+        if let Some(cartridge) = &self.cartridge {
+            sections.push((b"CART", cartridge));
+        }
+        sections
+    }
+    fn sections_mut(&mut self) -> Vec<(&[u8; 4], &mut dyn Snapshot)> {
+        let mut sections: Vec<(&[u8; 4], &mut dyn Snapshot)> = vec![
+            (b"REGS", &mut self.registers),
+            (b"APU ", &mut self.apu),
+            (b"JOYP", &mut self.joypad),
+            (b"SERL", &mut self.serial),
+            (b"INFR", &mut self.infrared),
+        ];
+        if let Some(boot_rom) = &mut self.boot_rom {
+            sections.push((b"BOOT", boot_rom));
+        }
+        if let Some(cartridge) = &mut self.cartridge {
+            sections.push((b"CART", cartridge));
+        }
+        sections
+    }
+    /// Write P1, which also sends the command packets of the SGB.
+    pub fn write_p1(&mut self, value: u8) {
+        self.joypad.write(value);
+        if let Some(sgb) = &mut self.sgb {
+            sgb.write_p1(value);
+            self.joypad.set_players(sgb.players());
+        }
+    }
+    /// Advance the console by `cycles` T-cycles.
+    pub fn tick(&mut self, cycles: u32) {
+        self.apu.tick(cycles);
+        self.serial.tick(cycles);
+        self.infrared.tick(cycles);
+        if let Some(cartridge) = &mut self.cartridge {
+            cartridge.tick(cycles, self.model.clock());
+        }
+    }
+}
+
+/// Advance all `consoles` and the `adapter` linking them, if any, by
+/// `cycles` T-cycles, interleaved by slices of `LOCKSTEP_CYCLES` so that
+/// linked consoles stay in sync.
+///
+/// The consoles always run in the same order, so the same inputs give
+/// the same results.
+pub fn tick_lockstep(
+    consoles: &mut [Console],
+    mut adapter: Option<&mut FourPlayerAdapter>,
+    cycles: u32,
+) {
+    let mut remaining = cycles;
+    while remaining > 0 {
+        let slice = remaining.min(LOCKSTEP_CYCLES);
+        if let Some(adapter) = &mut adapter {
+            adapter.tick(slice);
+        }
+        for console in consoles.iter_mut() {
+            console.tick(slice);
+        }
+        remaining -= slice;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::serial::{cable, SB, SC};
+
+    use super::*;
+
+    #[test]
+    fn linked_transfer() {
+        let (a, b) = cable();
+        let mut consoles = [Console::new(), Console::new()];
+        consoles[0].serial.connect(Box::new(a));
+        consoles[1].serial.connect(Box::new(b));
+        consoles[1].serial.write(SB, 0x22);
+        consoles[1].serial.write(SC, 0x80);
+        consoles[0].serial.write(SB, 0x11);
+        consoles[0].serial.write(SC, 0x81);
+
+        tick_lockstep(&mut consoles, None, 70224);
+        assert!(consoles[0].serial.take_interrupt());
+        assert!(consoles[1].serial.take_interrupt());
+        assert_eq!(consoles[0].serial.read(SB), 0x22);
+        assert_eq!(consoles[1].serial.read(SB), 0x11);
+    }
+
+    #[test]
+    fn restore_state() {
+        let mut console = Console::new();
+        console.apu.write(crate::apu::NR52, 0x80);
+        console.serial.write(SB, 0x42);
+        console.registers.pc = 0x0150;
+        let state = console.save_state(&[], 1);
+
+        console.apu.write(crate::apu::NR52, 0x00);
+        console.serial.write(SB, 0x00);
+        console.registers.pc = 0x0100;
+        let header = console.load_state(&state).unwrap();
+        assert_eq!(header.saved_at, 1);
+        assert!(console.apu.is_powered());
+        assert_eq!(console.serial.read(SB), 0x42);
+        assert_eq!(console.registers.pc, 0x0150);
+    }
+
+    #[test]
+    fn rejected_state_leaves_no_trace() {
+        let with_ram = |size| {
+            let mut rom = vec![0; 0x8000];
+            rom[0x147] = 0x03;
+            rom[0x149] = size;
+            let mut console = Console::new();
+            console.cartridge = Some(Cartridge::parse(&rom).unwrap());
+            console.apu.write(crate::apu::NR52, 0x80);
+            console.apu.start_vgm();
+            console
+        };
+        // A state whose RAM does not fit, rejected after the APU loaded.
+        let snapshot = with_ram(0x03).save_snapshot();
+        let mut console = with_ram(0x02);
+        let mut untouched = with_ram(0x02);
+        console.tick(1000);
+        untouched.tick(1000);
+        assert_eq!(
+            console.load_snapshot(&snapshot).unwrap_err(),
+            StateError::Truncated
+        );
+        assert!(!console.cartridge.as_ref().unwrap().is_dirty());
+        assert_eq!(console.apu.stop_vgm(), untouched.apu.stop_vgm());
+    }
+}
@@ -0,0 +1,313 @@
+//! The DMG-07 Four Player Adapter.
+//!
+//! The adapter clocks the serial ports of the four players, which all use
+//! the external clock. It starts in the ping phase, sending each player
+//! packets of `[0xFE, STAT, STAT, STAT]` where STAT holds the number of the
+//! player in its low bits and one bit per connected player in its high
+//! nibble. Players answer with `[0x88, 0x88, RATE, SIZE]`.
+//!
+//! Player 1 sending 0xAA for a whole packet starts the game: the adapter
+//! sends a packet of 0xCC then enters the transmission phase. There each
+//! round lasts `4 * SIZE` transfers: every player sends its SIZE bytes at
+//! the start of the round and receives the bytes all players sent in the
+//! previous round, player 1 first. A round where every player only sends
+//! 0xFF goes back to the ping phase.
+
+use crate::serial::{LinkCable, LinkMessage};
+
+pub const PLAYERS: usize = 4;
+
+const PING_HEADER: u8 = 0xFE;
+const ACK: u8 = 0x88;
+const START: u8 = 0xAA;
+const STARTING: u8 = 0xCC;
+const RESTART: u8 = 0xFF;
+const PACKET_LENGTH: usize = 4;
+/// Largest SIZE accepted from player 1.
+const MAX_SIZE: u8 = 16;
+/// T-cycles to shift a byte at 8192 Hz.
+const BYTE_CYCLES: u32 = 8 * 512;
+/// Extra T-cycles between bytes per unit of the low nibble of RATE.
+const RATE_CYCLES: u32 = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Ping,
+    /// Sending the packet of 0xCC announcing the transmission phase.
+    Starting,
+    Transmission,
+}
+
+pub struct FourPlayerAdapter {
+    ports: [Option<Box<dyn LinkCable>>; PLAYERS],
+    phase: Phase,
+    /// Players that acknowledged the last ping.
+    connected: [bool; PLAYERS],
+    acknowledged: [bool; PLAYERS],
+    /// Bytes of the current packet player 1 sent as 0xAA.
+    start_requests: usize,
+    rate: u8,
+    size: u8,
+    /// Next byte of the packet or round to send.
+    slot: usize,
+    /// Byte of the packet or round sent last, waiting for the replies.
+    sent: Option<usize>,
+    replies: [Option<u8>; PLAYERS],
+    countdown: u32,
+    /// Bytes each player sent during the current round.
+    incoming: [Vec<u8>; PLAYERS],
+    /// Bytes sent to every player during the current round.
+    outgoing: Vec<u8>,
+}
+impl Default for FourPlayerAdapter {
+    fn default() -> Self {
+        Self {
+            ports: Default::default(),
+            phase: Phase::Ping,
+            connected: [false; PLAYERS],
+            acknowledged: [false; PLAYERS],
+            start_requests: 0,
+            rate: 0,
+            size: 1,
+            slot: 0,
+            sent: None,
+            replies: [None; PLAYERS],
+            countdown: BYTE_CYCLES,
+            incoming: Default::default(),
+            outgoing: Vec::new(),
+        }
+    }
+}
+impl std::fmt::Debug for FourPlayerAdapter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FourPlayerAdapter")
+            .field("phase", &self.phase)
+            .field("connected", &self.connected)
+            .field("rate", &self.rate)
+            .field("size", &self.size)
+            .finish()
+    }
+}
+impl FourPlayerAdapter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Plug the cable of `player`, numbered from 0.
+    pub fn connect(&mut self, player: usize, link: Box<dyn LinkCable>) {
+        if let Some(port) = self.ports.get_mut(player) {
+            *port = Some(link);
+        }
+    }
+    pub fn phase(&self) -> Phase {
+        self.phase
+    }
+    /// Players that answered the last ping.
+    pub fn connected(&self) -> [bool; PLAYERS] {
+        self.connected
+    }
+    /// Advance the adapter by `cycles` T-cycles.
+    pub fn tick(&mut self, cycles: u32) {
+        self.receive();
+        let mut cycles = cycles;
+        while cycles >= self.countdown {
+            cycles -= self.countdown;
+            self.clock_byte();
+            self.countdown = self.byte_cycles();
+        }
+        self.countdown -= cycles;
+    }
+    fn receive(&mut self) {
+        for (port, reply) in self.ports.iter_mut().zip(&mut self.replies) {
+            while let Some(message) = port.as_mut().and_then(|link| link.receive()) {
+                // Players must use the external clock, their transfers are lost.
+                if let LinkMessage::Reply(byte) = message {
+                    *reply = Some(byte);
+                }
+            }
+        }
+    }
+    fn byte_cycles(&self) -> u32 {
+        match self.phase {
+            Phase::Transmission => BYTE_CYCLES + (self.rate & 0x0F) as u32 * RATE_CYCLES,
+            _ => BYTE_CYCLES,
+        }
+    }
+    fn length(&self) -> usize {
+        match self.phase {
+            Phase::Transmission => PLAYERS * self.size as usize,
+            _ => PACKET_LENGTH,
+        }
+    }
+    fn clock_byte(&mut self) {
+        if let Some(slot) = self.sent.take() {
+            for player in 0..PLAYERS {
+                if let Some(reply) = self.replies[player].take() {
+                    self.absorb(player, slot, reply);
+                }
+            }
+            if slot + 1 == self.length() {
+                self.finish();
+            }
+        }
+        let slot = self.slot;
+        for player in 0..PLAYERS {
+            let byte = self.byte_for(player, slot);
+            if let Some(link) = &mut self.ports[player] {
+                link.send(LinkMessage::Transfer(byte));
+            }
+        }
+        self.sent = Some(slot);
+        self.slot = (slot + 1) % self.length();
+    }
+    fn byte_for(&self, player: usize, slot: usize) -> u8 {
+        match self.phase {
+            Phase::Ping if slot == 0 => PING_HEADER,
+            Phase::Ping => {
+                let connected = (0..PLAYERS)
+                    .filter(|&player| self.connected[player])
+                    .fold(0, |mask, player| mask | 0x10 << player);
+                connected | (player as u8 + 1)
+            }
+            Phase::Starting => STARTING,
+            Phase::Transmission => self.outgoing.get(slot).copied().unwrap_or_default(),
+        }
+    }
+    /// Handle the byte `player` sent during `slot`.
+    fn absorb(&mut self, player: usize, slot: usize, byte: u8) {
+        match self.phase {
+            Phase::Ping => {
+                if player == 0 && byte == START {
+                    self.start_requests += 1;
+                }
+                match slot {
+                    0 => self.acknowledged[player] = byte == ACK || byte == START,
+                    2 if player == 0 && byte != START => self.rate = byte,
+                    3 if player == 0 && byte != START => self.size = byte.clamp(1, MAX_SIZE),
+                    _ => {}
+                }
+            }
+            Phase::Starting => {}
+            Phase::Transmission => {
+                if slot < self.size as usize {
+                    self.incoming[player].push(byte);
+                }
+            }
+        }
+    }
+    /// End of a packet or round.
+    fn finish(&mut self) {
+        match self.phase {
+            Phase::Ping => {
+                self.connected = std::mem::take(&mut self.acknowledged);
+                if std::mem::take(&mut self.start_requests) == PACKET_LENGTH {
+                    self.phase = Phase::Starting;
+                }
+            }
+            Phase::Starting => {
+                self.phase = Phase::Transmission;
+                self.outgoing = vec![0; PLAYERS * self.size as usize];
+            }
+            Phase::Transmission => {
+                let size = self.size as usize;
+                let restart = (0..PLAYERS)
+                    .filter(|&player| self.connected[player])
+                    .all(|player| {
+                        self.incoming[player].len() == size
+                            && self.incoming[player].iter().all(|&byte| byte == RESTART)
+                    });
+                self.outgoing.clear();
+                for player in 0..PLAYERS {
+                    let mut packet = std::mem::take(&mut self.incoming[player]);
+                    if !self.connected[player] {
+                        packet.clear();
+                    }
+                    packet.resize(size, 0);
+                    self.outgoing.extend(packet);
+                }
+                if restart {
+                    self.phase = Phase::Ping;
+                    self.connected = [false; PLAYERS];
+                }
+            }
+        }
+        self.slot = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::serial::{cable, WireLink};
+
+    use super::*;
+
+    /// Clock one byte, `reply` gives the answer of each player to it.
+    fn exchange(
+        adapter: &mut FourPlayerAdapter,
+        players: &mut [WireLink],
+        reply: impl Fn(usize, u8) -> u8,
+    ) -> Vec<u8> {
+        adapter.tick(adapter.countdown);
+        let mut received = Vec::new();
+        for (player, link) in players.iter_mut().enumerate() {
+            let Some(LinkMessage::Transfer(byte)) = link.receive() else {
+                panic!("player {} was not clocked", player + 1);
+            };
+            link.send(LinkMessage::Reply(reply(player, byte)));
+            received.push(byte);
+        }
+        received
+    }
+
+    #[test]
+    fn ping_then_transmit() {
+        let mut adapter = FourPlayerAdapter::new();
+        let mut players = Vec::new();
+        for player in 0..PLAYERS {
+            let (port, end) = cable();
+            adapter.connect(player, Box::new(port));
+            players.push(end);
+        }
+        let ping = |_, _| ACK;
+        for _ in 0..PACKET_LENGTH * 2 {
+            exchange(&mut adapter, &mut players, ping);
+        }
+        assert_eq!(exchange(&mut adapter, &mut players, ping), vec![0xFE; 4]);
+        assert_eq!(
+            exchange(&mut adapter, &mut players, ping),
+            vec![0xF1, 0xF2, 0xF3, 0xF4]
+        );
+        assert_eq!(adapter.connected(), [true; 4]);
+
+        // RATE 0 and SIZE 1, then player 1 starts the game.
+        exchange(&mut adapter, &mut players, |_, _| 0);
+        exchange(&mut adapter, &mut players, |_, _| 1);
+        for _ in 0..PACKET_LENGTH {
+            exchange(&mut adapter, &mut players, |player, _| {
+                if player == 0 {
+                    START
+                } else {
+                    ACK
+                }
+            });
+        }
+        for _ in 0..PACKET_LENGTH {
+            assert_eq!(
+                exchange(&mut adapter, &mut players, ping),
+                vec![STARTING; 4]
+            );
+        }
+
+        // Each player sends its number, then receives everyone's.
+        let send_number = |player: usize, _| player as u8 + 1;
+        for _ in 0..PLAYERS {
+            exchange(&mut adapter, &mut players, send_number);
+        }
+        assert_eq!(adapter.phase(), Phase::Transmission);
+        for number in 1..=4 {
+            assert_eq!(
+                exchange(&mut adapter, &mut players, send_number),
+                vec![number; 4]
+            );
+        }
+    }
+}
@@ -0,0 +1,304 @@
+//! Game Boy Sound System (`.gbs`) music rips.
+//!
+//! A GBS file is a 0x70 byte header followed by the music driver code and
+//! data, which is loaded at `load_address`. The player calls `init` once
+//! with the track number in A, then calls `play` either at every vertical
+//! blank or at the rate programmed through the timer registers.
+
+use std::fmt::Display;
+
+use crate::apu::CPU_CLOCK;
+
+pub const HEADER_SIZE: usize = 0x70;
+/// Refresh rate of the DMG LCD, the default rate of the play calls.
+const VBLANK_RATE: f64 = 59.7275;
+/// Input clock of the timer for each value of the TAC clock select bits.
+const TIMER_CLOCKS: [u32; 4] = [4096, 262_144, 65_536, 16_384];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GbsError {
+    TooShort(usize),
+    BadMagic,
+    UnsupportedVersion(u8),
+    BadLoadAddress(u16),
+    NoSongs,
+}
+impl Display for GbsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GbsError::TooShort(len) => write!(f, "file too short for a GBS header ({len} bytes)"),
+            GbsError::BadMagic => write!(f, "missing GBS signature"),
+            GbsError::UnsupportedVersion(version) => {
+                write!(f, "unsupported GBS version {version}")
+            }
+            GbsError::BadLoadAddress(address) => {
+                write!(f, "load address {address:#06X} is outside the ROM area")
+            }
+            GbsError::NoSongs => write!(f, "the file contains no songs"),
+        }
+    }
+}
+impl std::error::Error for GbsError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GbsHeader {
+    pub version: u8,
+    pub song_count: u8,
+    /// First song to play, 1 based as stored in the file.
+    pub first_song: u8,
+    pub load_address: u16,
+    pub init_address: u16,
+    pub play_address: u16,
+    pub stack_pointer: u16,
+    pub timer_modulo: u8,
+    pub timer_control: u8,
+    pub title: String,
+    pub author: String,
+    pub copyright: String,
+}
+impl GbsHeader {
+    /// Whether `play` is driven by the timer interrupt instead of vblank.
+    pub fn uses_timer(&self) -> bool {
+        self.timer_control & 0x04 != 0
+    }
+    /// How many times per second `play` must be called.
+    pub fn play_rate(&self) -> f64 {
+        if !self.uses_timer() {
+            return VBLANK_RATE;
+        }
+        let mut clock = TIMER_CLOCKS[(self.timer_control & 0x03) as usize] as f64;
+        // Bit 7 asks for CGB double speed mode.
+        if self.timer_control & 0x80 != 0 {
+            clock *= 2.;
+        }
+        clock / (256 - self.timer_modulo as u32) as f64
+    }
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+fn read_string(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).trim().to_string()
+}
+
+#[derive(Debug, Clone)]
+pub struct Gbs {
+    pub header: GbsHeader,
+    data: Vec<u8>,
+}
+impl Gbs {
+    pub fn parse(bytes: &[u8]) -> Result<Self, GbsError> {
+        if bytes.len() < HEADER_SIZE {
+            return Err(GbsError::TooShort(bytes.len()));
+        }
+        if &bytes[0..3] != b"GBS" {
+            return Err(GbsError::BadMagic);
+        }
+        let version = bytes[3];
+        if version != 1 {
+            return Err(GbsError::UnsupportedVersion(version));
+        }
+        let header = GbsHeader {
+            version,
+            song_count: bytes[4],
+            first_song: bytes[5],
+            load_address: read_u16(bytes, 0x06),
+            init_address: read_u16(bytes, 0x08),
+            play_address: read_u16(bytes, 0x0A),
+            stack_pointer: read_u16(bytes, 0x0C),
+            timer_modulo: bytes[0x0E],
+            timer_control: bytes[0x0F],
+            title: read_string(&bytes[0x10..0x30]),
+            author: read_string(&bytes[0x30..0x50]),
+            copyright: read_string(&bytes[0x50..0x70]),
+        };
+        if header.song_count == 0 {
+            return Err(GbsError::NoSongs);
+        }
+        if !(0x0400..0x8000).contains(&header.load_address) {
+            return Err(GbsError::BadLoadAddress(header.load_address));
+        }
+        Ok(Self {
+            header,
+            data: bytes[HEADER_SIZE..].to_vec(),
+        })
+    }
+    /// Build the cartridge image the driver expects: the data at the load
+    /// address and the RST vectors redirected to the start of the data.
+    pub fn rom(&self) -> Vec<u8> {
+        let load_address = self.header.load_address as usize;
+        let size = (load_address + self.data.len()).next_multiple_of(0x4000);
+        let mut rom = vec![0xFF; size.max(0x8000)];
+        for rst in (0x00..0x40).step_by(8) {
+            let [low, high] = (self.header.load_address + rst as u16).to_le_bytes();
+            // JP load_address + rst
+            rom[rst] = 0xC3;
+            rom[rst + 1] = low;
+            rom[rst + 2] = high;
+        }
+        rom[load_address..load_address + self.data.len()].copy_from_slice(&self.data);
+        rom
+    }
+}
+
+/// A routine the CPU has to run on behalf of the player.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GbsCall {
+    /// Call `address` with the 0 based `song` in A and SP set to `stack_pointer`.
+    Init {
+        address: u16,
+        song: u8,
+        stack_pointer: u16,
+    },
+    Play {
+        address: u16,
+    },
+}
+
+/// Schedules the init and play calls of a GBS rip.
+#[derive(Debug, Clone)]
+pub struct GbsPlayer {
+    gbs: Gbs,
+    track: u8,
+    cycles: u64,
+    play_cycles: f64,
+    init: Option<GbsCall>,
+    /// Like the interrupt flag, play calls due before the last one ran
+    /// are merged into one.
+    play_pending: bool,
+}
+impl GbsPlayer {
+    pub fn new(gbs: Gbs) -> Self {
+        let first = gbs.header.first_song.clamp(1, gbs.header.song_count) - 1;
+        let mut player = Self {
+            gbs,
+            track: 0,
+            cycles: 0,
+            play_cycles: 0.,
+            init: None,
+            play_pending: false,
+        };
+        player.select(first);
+        player
+    }
+    pub fn header(&self) -> &GbsHeader {
+        &self.gbs.header
+    }
+    pub fn gbs(&self) -> &Gbs {
+        &self.gbs
+    }
+    /// Current track, 0 based.
+    pub fn track(&self) -> u8 {
+        self.track
+    }
+    /// Start playing `track` (0 based) from the beginning.
+    pub fn select(&mut self, track: u8) {
+        self.track = track % self.gbs.header.song_count;
+        self.cycles = 0;
+        self.play_cycles = 0.;
+        self.play_pending = false;
+        self.init = Some(GbsCall::Init {
+            address: self.gbs.header.init_address,
+            song: self.track,
+            stack_pointer: self.gbs.header.stack_pointer,
+        });
+    }
+    pub fn next_track(&mut self) {
+        self.select((self.track + 1) % self.gbs.header.song_count);
+    }
+    pub fn previous_track(&mut self) {
+        let count = self.gbs.header.song_count as u16;
+        self.select(((self.track as u16 + count - 1) % count) as u8);
+    }
+    /// Seconds since the current track was selected.
+    pub fn elapsed(&self) -> f64 {
+        self.cycles as f64 / CPU_CLOCK as f64
+    }
+    /// Advance by `cycles` T-cycles, raising a play call if one came due.
+    pub fn tick(&mut self, cycles: u32) {
+        let period = CPU_CLOCK as f64 / self.gbs.header.play_rate();
+        self.cycles += cycles as u64;
+        self.play_cycles += cycles as f64;
+        if self.play_cycles >= period {
+            self.play_cycles %= period;
+            self.play_pending = true;
+        }
+    }
+    /// Next routine the CPU should run, the init call before any play call.
+    pub fn next_call(&mut self) -> Option<GbsCall> {
+        if let Some(init) = self.init.take() {
+            return Some(init);
+        }
+        std::mem::take(&mut self.play_pending).then_some(GbsCall::Play {
+            address: self.gbs.header.play_address,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gbs_bytes(timer_modulo: u8, timer_control: u8) -> Vec<u8> {
+        let mut bytes = vec![0; HEADER_SIZE];
+        bytes[0..4].copy_from_slice(b"GBS\x01");
+        bytes[4] = 3;
+        bytes[5] = 2;
+        bytes[0x06..0x08].copy_from_slice(&0x3F00u16.to_le_bytes());
+        bytes[0x08..0x0A].copy_from_slice(&0x3F10u16.to_le_bytes());
+        bytes[0x0A..0x0C].copy_from_slice(&0x3F20u16.to_le_bytes());
+        bytes[0x0C..0x0E].copy_from_slice(&0xDFFFu16.to_le_bytes());
+        bytes[0x0E] = timer_modulo;
+        bytes[0x0F] = timer_control;
+        bytes[0x10..0x15].copy_from_slice(b"Title");
+        bytes.extend_from_slice(&[0xC9; 0x30]);
+        bytes
+    }
+
+    #[test]
+    fn parse_header() {
+        let gbs = Gbs::parse(&gbs_bytes(0, 0)).unwrap();
+        assert_eq!(gbs.header.song_count, 3);
+        assert_eq!(gbs.header.load_address, 0x3F00);
+        assert_eq!(gbs.header.title, "Title");
+        assert_eq!(gbs.header.author, "");
+        let rom = gbs.rom();
+        assert_eq!(rom.len(), 0x8000);
+        assert_eq!(&rom[0x38..0x3B], &[0xC3, 0x38, 0x3F]);
+        assert_eq!(rom[0x3F00], 0xC9);
+        assert_eq!(Gbs::parse(b"NSF").unwrap_err(), GbsError::TooShort(3));
+    }
+
+    #[test]
+    fn timer_driven_play_calls() {
+        // 4096 Hz / (256 - 0xC0) = 64 calls per second.
+        let gbs = Gbs::parse(&gbs_bytes(0xC0, 0x04)).unwrap();
+        assert_eq!(gbs.header.play_rate(), 64.);
+        let mut player = GbsPlayer::new(gbs);
+        assert_eq!(
+            player.next_call(),
+            Some(GbsCall::Init {
+                address: 0x3F10,
+                song: 1,
+                stack_pointer: 0xDFFF
+            })
+        );
+        let mut plays = 0;
+        for _ in 0..CPU_CLOCK / 4096 {
+            player.tick(4096);
+            if let Some(GbsCall::Play { address: 0x3F20 }) = player.next_call() {
+                plays += 1;
+            }
+        }
+        assert_eq!(plays, 64);
+        // Calls the CPU fell behind on do not pile up.
+        player.tick(CPU_CLOCK);
+        assert_eq!(player.next_call(), Some(GbsCall::Play { address: 0x3F20 }));
+        assert_eq!(player.next_call(), None);
+        player.previous_track();
+        player.previous_track();
+        assert_eq!(player.track(), 2);
+    }
+}
@@ -0,0 +1,223 @@
+//! The infrared port of the CGB: RP (0xFF56).
+//!
+//! Bit 0 lights the LED, bit 1 reads 0 while the receiver sees light and
+//! bits 6-7 must both be set for the receiver to work. The register does
+//! not exist on the DMG.
+
+use std::collections::VecDeque;
+
+use crate::{
+    serial::WireLink,
+    state::{Snapshot, StateError, StateReader, StateWriter},
+};
+
+pub const RP: u16 = 0xFF56;
+
+const LED: u8 = 0x01;
+const NO_LIGHT: u8 = 0x02;
+const READ_ENABLE: u8 = 0xC0;
+
+/// The LED of the other console turning on or off.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Signal {
+    /// Clock of the sender when the LED changed, in T-cycles since its
+    /// port was connected. The receiver replays the changes at the same
+    /// pace, which keeps the length of the pulses.
+    pub at: u64,
+    pub on: bool,
+}
+
+/// What the receiver of one console sees of the LED of another.
+pub trait InfraredLink: Send {
+    fn send(&mut self, signal: Signal);
+    /// Next change of the other LED, if any arrived.
+    fn receive(&mut self) -> Option<Signal>;
+}
+impl InfraredLink for WireLink<Signal> {
+    fn send(&mut self, signal: Signal) {
+        self.push(signal);
+    }
+    fn receive(&mut self) -> Option<Signal> {
+        self.pop()
+    }
+}
+
+/// Both ends of an infrared link between two consoles of the same process.
+pub fn beam() -> (WireLink<Signal>, WireLink<Signal>) {
+    WireLink::pair()
+}
+
+#[derive(Default)]
+pub struct Infrared {
+    cgb: bool,
+    control: u8,
+    /// T-cycles since the port was connected, the time of the signals.
+    cycles: u64,
+    /// How far behind the clock of the sender the signals are replayed,
+    /// set by the first one received and raised when one arrives late.
+    delay: Option<u64>,
+    /// Whether the light of the other console reaches the receiver.
+    light: bool,
+    /// Changes of the other LED waiting for the clock to reach them.
+    pending: VecDeque<Signal>,
+    link: Option<Box<dyn InfraredLink>>,
+}
+impl std::fmt::Debug for Infrared {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Infrared")
+            .field("control", &self.control)
+            .field("light", &self.light)
+            .field("connected", &self.link.is_some())
+            .finish()
+    }
+}
+impl Infrared {
+    /// The port of a DMG, which does not have one.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn new_cgb() -> Self {
+        Self {
+            cgb: true,
+            ..Default::default()
+        }
+    }
+    /// Point the port at another console, replacing the previous one.
+    pub fn connect(&mut self, link: Box<dyn InfraredLink>) {
+        self.link = Some(link);
+        self.cycles = 0;
+        self.delay = None;
+    }
+    pub fn disconnect(&mut self) -> Option<Box<dyn InfraredLink>> {
+        self.link.take()
+    }
+    pub fn is_connected(&self) -> bool {
+        self.link.is_some()
+    }
+    pub fn read(&self, address: u16) -> u8 {
+        if address != RP || !self.cgb {
+            return 0xFF;
+        }
+        let receiving = self.control & READ_ENABLE == READ_ENABLE && self.light;
+        let signal = if receiving { 0 } else { NO_LIGHT };
+        self.control | signal | 0x3C
+    }
+    pub fn write(&mut self, address: u16, value: u8) {
+        if address != RP || !self.cgb {
+            return;
+        }
+        let was_on = self.control & LED != 0;
+        self.control = value & (READ_ENABLE | LED);
+        let on = self.control & LED != 0;
+        if on != was_on {
+            if let Some(link) = &mut self.link {
+                link.send(Signal {
+                    at: self.cycles,
+                    on,
+                });
+            }
+        }
+    }
+    /// Advance the port by `cycles` T-cycles.
+    pub fn tick(&mut self, cycles: u32) {
+        self.cycles += cycles as u64;
+        let now = self.cycles;
+        while let Some(mut signal) = self.link.as_mut().and_then(|link| link.receive()) {
+            let delay = self.delay.get_or_insert(0);
+            *delay = (*delay).max(now.saturating_sub(signal.at));
+            signal.at += *delay;
+            self.pending.push_back(signal);
+        }
+        while let Some(signal) = self.pending.front() {
+            if signal.at > now {
+                break;
+            }
+            self.light = signal.on;
+            self.pending.pop_front();
+        }
+    }
+}
+
+/// Signals on their way from the other console are dropped.
+impl Snapshot for Infrared {
+    fn save(&self, writer: &mut StateWriter) {
+        writer.u8(self.control);
+        writer.u64(self.cycles);
+        writer.bool(self.light);
+    }
+    fn load(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.control = reader.u8()? & (READ_ENABLE | LED);
+        // The clock is shared with the other end and goes on, how late
+        // its signals arrive is measured again.
+        reader.u64()?;
+        self.light = reader.bool()?;
+        self.pending.clear();
+        self.delay = None;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn receive_other_led() {
+        let (a, b) = beam();
+        let mut sender = Infrared::new_cgb();
+        sender.connect(Box::new(a));
+        let mut receiver = Infrared::new_cgb();
+        receiver.connect(Box::new(b));
+        receiver.write(RP, READ_ENABLE);
+        assert_eq!(receiver.read(RP), 0xFE);
+
+        sender.tick(100);
+        sender.write(RP, LED);
+        sender.tick(50);
+        sender.write(RP, 0);
+        receiver.tick(99);
+        assert_eq!(receiver.read(RP) & NO_LIGHT, NO_LIGHT);
+        receiver.tick(1);
+        assert_eq!(receiver.read(RP) & NO_LIGHT, 0);
+        receiver.tick(49);
+        assert_eq!(receiver.read(RP) & NO_LIGHT, 0);
+        receiver.tick(1);
+        assert_eq!(receiver.read(RP) & NO_LIGHT, NO_LIGHT);
+
+        assert_eq!(Infrared::new().read(RP), 0xFF);
+    }
+
+    #[test]
+    fn replay_late_signals_at_their_pace() {
+        let (a, b) = beam();
+        let mut sender = Infrared::new_cgb();
+        sender.connect(Box::new(a));
+        let mut receiver = Infrared::new_cgb();
+        receiver.connect(Box::new(b));
+        receiver.write(RP, READ_ENABLE);
+
+        // A pulse that arrives a whole frame after it was sent.
+        receiver.tick(70_000);
+        sender.tick(100);
+        sender.write(RP, LED);
+        sender.tick(50);
+        sender.write(RP, 0);
+        receiver.tick(1);
+        assert_eq!(receiver.read(RP) & NO_LIGHT, 0);
+        receiver.tick(49);
+        assert_eq!(receiver.read(RP) & NO_LIGHT, 0);
+        receiver.tick(1);
+        assert_eq!(receiver.read(RP) & NO_LIGHT, NO_LIGHT);
+
+        // The link clock goes on across a state load.
+        let mut writer = StateWriter::default();
+        receiver.save(&mut writer);
+        let state = writer.into_bytes();
+        receiver.tick(1000);
+        receiver.load(&mut StateReader::new(&state)).unwrap();
+        sender.tick(10);
+        sender.write(RP, LED);
+        receiver.tick(1);
+        assert_eq!(receiver.read(RP) & NO_LIGHT, 0);
+    }
+}
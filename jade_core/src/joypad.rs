@@ -0,0 +1,240 @@
+//! The P1/JOYP register at 0xFF00.
+//!
+//! The eight buttons are wired as a 2x4 matrix: writing 0 to bit 4 selects
+//! the directions, writing 0 to bit 5 selects the action buttons, and the
+//! lower nibble reads 0 for every pressed button of the selected groups.
+//!
+//! When a Super Game Boy game enables several joypads with MLT_REQ,
+//! deselecting both groups reads the number of the current joypad,
+//! 0xF for the first one down to 0xC for the fourth, and each rising edge
+//! of P15 moves on to the next joypad.
+
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+pub const P1: u16 = 0xFF00;
+/// Bit of the joypad interrupt in IE and IF.
+pub const JOYPAD_INTERRUPT: u8 = 1 << 4;
+
+const SELECT_DIRECTIONS: u8 = 1 << 4;
+const SELECT_BUTTONS: u8 = 1 << 5;
+/// Most joypads a Super Game Boy reads.
+pub const MAX_PLAYERS: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Button {
+    Right,
+    Left,
+    Up,
+    Down,
+    A,
+    B,
+    Select,
+    Start,
+}
+impl Button {
+    pub const ALL: [Button; 8] = [
+        Button::Right,
+        Button::Left,
+        Button::Up,
+        Button::Down,
+        Button::A,
+        Button::B,
+        Button::Select,
+        Button::Start,
+    ];
+    /// Bit of the button in the pressed mask: directions in the lower
+    /// nibble, action buttons in the upper one, both in P1 line order.
+    fn mask(self) -> u8 {
+        1 << self as u8
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            Button::Right => "right",
+            Button::Left => "left",
+            Button::Up => "up",
+            Button::Down => "down",
+            Button::A => "a",
+            Button::B => "b",
+            Button::Select => "select",
+            Button::Start => "start",
+        }
+    }
+    pub fn from_name(name: &str) -> Option<Button> {
+        Button::ALL
+            .into_iter()
+            .find(|button| button.name().eq_ignore_ascii_case(name))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Joypad {
+    /// Select bits 4 and 5 as last written, 0 means selected.
+    select: u8,
+    /// Buttons pressed on each joypad.
+    pressed: [u8; MAX_PLAYERS],
+    players: usize,
+    /// Joypad read through P1.
+    current: usize,
+    interrupt: bool,
+}
+impl Default for Joypad {
+    fn default() -> Self {
+        Self {
+            select: SELECT_DIRECTIONS | SELECT_BUTTONS,
+            pressed: [0; MAX_PLAYERS],
+            players: 1,
+            current: 0,
+            interrupt: false,
+        }
+    }
+}
+impl Joypad {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Input lines P10-P13, a bit is 0 when a selected button is pressed.
+    fn lines(&self) -> u8 {
+        let pressed = self.pressed[self.current];
+        let mut low = 0;
+        if self.select & SELECT_DIRECTIONS == 0 {
+            low |= pressed & 0x0F;
+        }
+        if self.select & SELECT_BUTTONS == 0 {
+            low |= pressed >> 4;
+        }
+        if self.players > 1 && self.select == SELECT_DIRECTIONS | SELECT_BUTTONS {
+            low = self.current as u8;
+        }
+        !low & 0x0F
+    }
+    /// Apply `change` and request the interrupt if an input line went low.
+    fn update<F: FnOnce(&mut Self)>(&mut self, change: F) {
+        let before = self.lines();
+        change(self);
+        if before & !self.lines() != 0 {
+            self.interrupt = true;
+        }
+    }
+    pub fn read(&self) -> u8 {
+        0xC0 | self.select | self.lines()
+    }
+    pub fn write(&mut self, value: u8) {
+        self.update(|joypad| {
+            let select = value & (SELECT_DIRECTIONS | SELECT_BUTTONS);
+            if joypad.players > 1 && !joypad.select & select & SELECT_BUTTONS != 0 {
+                joypad.current = (joypad.current + 1) % joypad.players;
+            }
+            joypad.select = select;
+        });
+    }
+    /// Number of joypads read in turn, as requested by MLT_REQ.
+    pub fn set_players(&mut self, players: usize) {
+        let players = players.clamp(1, MAX_PLAYERS);
+        if players != self.players {
+            self.players = players;
+            self.current = 0;
+        }
+    }
+    pub fn players(&self) -> usize {
+        self.players
+    }
+    pub fn press(&mut self, button: Button) {
+        self.press_player(0, button);
+    }
+    pub fn release(&mut self, button: Button) {
+        self.release_player(0, button);
+    }
+    pub fn is_pressed(&self, button: Button) -> bool {
+        self.is_pressed_player(0, button)
+    }
+    /// Press `button` on the joypad of `player`, numbered from 0.
+    pub fn press_player(&mut self, player: usize, button: Button) {
+        if player < MAX_PLAYERS {
+            self.update(|joypad| joypad.pressed[player] |= button.mask());
+        }
+    }
+    pub fn release_player(&mut self, player: usize, button: Button) {
+        if let Some(pressed) = self.pressed.get_mut(player) {
+            *pressed &= !button.mask();
+        }
+    }
+    pub fn is_pressed_player(&self, player: usize, button: Button) -> bool {
+        self.pressed
+            .get(player)
+            .is_some_and(|pressed| pressed & button.mask() != 0)
+    }
+    /// Whether the joypad interrupt was requested since the last call.
+    pub fn take_interrupt(&mut self) -> bool {
+        std::mem::take(&mut self.interrupt)
+    }
+}
+
+/// The buttons held are left out, they follow the input of the players.
+impl Snapshot for Joypad {
+    fn save(&self, writer: &mut StateWriter) {
+        writer.u8(self.select);
+        writer.u8(self.players as u8);
+        writer.u8(self.current as u8);
+        writer.bool(self.interrupt);
+    }
+    fn load(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.select = reader.u8()? & (SELECT_DIRECTIONS | SELECT_BUTTONS);
+        self.players = (reader.u8()? as usize).clamp(1, MAX_PLAYERS);
+        self.current = reader.u8()? as usize % self.players;
+        self.interrupt = reader.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_selected_group() {
+        let mut joypad = Joypad::new();
+        joypad.press(Button::Start);
+        joypad.press(Button::Up);
+        assert_eq!(joypad.read(), 0xFF);
+        assert!(!joypad.take_interrupt());
+
+        joypad.write(0x20);
+        assert_eq!(joypad.read(), 0xEB);
+        joypad.write(0x10);
+        assert_eq!(joypad.read(), 0xD7);
+        joypad.write(0x00);
+        assert_eq!(joypad.read(), 0xC3);
+    }
+
+    #[test]
+    fn interrupt_on_falling_edge() {
+        let mut joypad = Joypad::new();
+        joypad.write(0x10);
+        joypad.press(Button::A);
+        assert!(joypad.take_interrupt());
+        assert!(!joypad.take_interrupt());
+        // A is already low, B shares no line with it.
+        joypad.press(Button::B);
+        assert!(joypad.take_interrupt());
+        joypad.press(Button::Left);
+        assert!(!joypad.take_interrupt());
+    }
+
+    #[test]
+    fn read_joypads_in_turn() {
+        let mut joypad = Joypad::new();
+        joypad.set_players(2);
+        joypad.press_player(1, Button::A);
+        joypad.write(0x30);
+        assert_eq!(joypad.read() & 0x0F, 0x0F);
+        joypad.write(0x10);
+        assert_eq!(joypad.read() & 0x0F, 0x0F);
+        // P15 goes back high: the second joypad.
+        joypad.write(0x30);
+        assert_eq!(joypad.read() & 0x0F, 0x0E);
+        joypad.write(0x10);
+        assert_eq!(joypad.read() & 0x0F, 0x0E);
+        joypad.write(0x30);
+        assert_eq!(joypad.read() & 0x0F, 0x0F);
+    }
+}
@@ -1,14 +1,15 @@
-pub fn add(left: u64, right: u64) -> u64 {
-    left + right
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn it_works() {
-        let result = add(2, 2);
-        assert_eq!(result, 4);
-    }
-}
+pub mod apu;
+pub mod boot;
+pub mod cartridge;
+pub mod console;
+pub mod four_player;
+pub mod gbs;
+pub mod infrared;
+pub mod joypad;
+pub mod model;
+pub mod printer;
+pub mod rtc;
+pub mod serial;
+pub mod sgb;
+pub mod state;
+pub mod vgm;
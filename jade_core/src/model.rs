@@ -0,0 +1,130 @@
+//! The hardware models, which differ in the state the boot ROM leaves and
+//! in a few behaviours games rely on to tell them apart.
+
+use crate::apu::CPU_CLOCK;
+
+/// Clock of the Game Boy inside the original Super Game Boy, derived from
+/// the SNES clock: about 2.4% faster than a handheld.
+const SGB_CLOCK: u32 = 4_295_454;
+
+const CGB_FLAG: usize = 0x143;
+const SGB_FLAG: usize = 0x146;
+const OLD_LICENSEE: usize = 0x14B;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Model {
+    /// The first DMG revision, with its own boot ROM.
+    Dmg0,
+    #[default]
+    Dmg,
+    /// Game Boy Pocket and Light, A reads 0xFF after boot.
+    Mgb,
+    /// Super Game Boy, clocked from the SNES.
+    Sgb,
+    /// Super Game Boy 2, A reads 0xFF after boot and the clock is accurate.
+    Sgb2,
+    /// The first CGB revision.
+    Cgb0,
+    Cgb,
+    /// Game Boy Advance, bit 0 of B is set after boot.
+    Agb,
+}
+impl Model {
+    pub const ALL: [Model; 8] = [
+        Model::Dmg0,
+        Model::Dmg,
+        Model::Mgb,
+        Model::Sgb,
+        Model::Sgb2,
+        Model::Cgb0,
+        Model::Cgb,
+        Model::Agb,
+    ];
+    pub fn name(self) -> &'static str {
+        match self {
+            Model::Dmg0 => "dmg0",
+            Model::Dmg => "dmg",
+            Model::Mgb => "mgb",
+            Model::Sgb => "sgb",
+            Model::Sgb2 => "sgb2",
+            Model::Cgb0 => "cgb0",
+            Model::Cgb => "cgb",
+            Model::Agb => "agb",
+        }
+    }
+    pub fn from_name(name: &str) -> Option<Model> {
+        Model::ALL
+            .into_iter()
+            .find(|model| model.name().eq_ignore_ascii_case(name))
+    }
+    /// The model a cartridge asks for in its header: a CGB for CGB games,
+    /// a Super Game Boy for games with SGB functions, a DMG otherwise.
+    pub fn from_header(rom: &[u8]) -> Model {
+        let byte = |address: usize| rom.get(address).copied().unwrap_or_default();
+        if byte(CGB_FLAG) & 0x80 != 0 {
+            Model::Cgb
+        } else if byte(SGB_FLAG) == 0x03 && byte(OLD_LICENSEE) == 0x33 {
+            Model::Sgb
+        } else {
+            Model::Dmg
+        }
+    }
+    pub fn is_cgb(self) -> bool {
+        matches!(self, Model::Cgb0 | Model::Cgb | Model::Agb)
+    }
+    pub fn is_sgb(self) -> bool {
+        matches!(self, Model::Sgb | Model::Sgb2)
+    }
+    /// T-cycles per second.
+    pub fn clock(self) -> u32 {
+        match self {
+            Model::Sgb => SGB_CLOCK,
+            _ => CPU_CLOCK,
+        }
+    }
+    /// DIV when the cartridge starts. The SGB hands over after a delay
+    /// set by the SNES, so its value varies and 0 is used.
+    pub fn div(self) -> u8 {
+        match self {
+            Model::Dmg0 => 0x18,
+            Model::Dmg | Model::Mgb => 0xAB,
+            Model::Sgb | Model::Sgb2 => 0x00,
+            Model::Cgb0 | Model::Cgb | Model::Agb => 0x1E,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn select_from_header() {
+        let mut rom = vec![0; 0x150];
+        assert_eq!(Model::from_header(&rom), Model::Dmg);
+        rom[SGB_FLAG] = 0x03;
+        rom[OLD_LICENSEE] = 0x33;
+        assert_eq!(Model::from_header(&rom), Model::Sgb);
+        rom[CGB_FLAG] = 0xC0;
+        assert_eq!(Model::from_header(&rom), Model::Cgb);
+        assert_eq!(Model::from_name("SGB2"), Some(Model::Sgb2));
+    }
+
+    #[test]
+    fn div_after_boot() {
+        use crate::{boot::Registers, console::Console};
+
+        assert_eq!(Registers::after_boot(Model::Dmg).div, 0xAB);
+        assert_eq!(Registers::after_boot(Model::Cgb).div, 0x1E);
+        assert_ne!(
+            Registers::after_boot(Model::Dmg0).div,
+            Registers::after_boot(Model::Dmg).div
+        );
+        // Kept in the state of the console.
+        let mut console = Console::with_model(Model::Cgb);
+        let state = console.save_state(&[], 0);
+        console.registers.div = 0;
+        console.load_state(&state).unwrap();
+        assert_eq!(console.registers.div, 0x1E);
+    }
+}
@@ -0,0 +1,346 @@
+//! The Game Boy Printer, plugged in the serial port.
+//!
+//! The Game Boy sends packets made of the magic bytes 0x88 0x33, a
+//! command, a compression flag, the length of the data (little endian),
+//! the data and a checksum of everything after the magic bytes. It then
+//! clocks two more bytes, to which the printer answers 0x81 and its
+//! status.
+
+use std::collections::VecDeque;
+
+use crate::serial::{LinkCable, LinkMessage};
+
+/// Width of the paper, in pixels.
+pub const PAPER_WIDTH: usize = 160;
+
+const MAGIC: [u8; 2] = [0x88, 0x33];
+const DEVICE_ID: u8 = 0x81;
+const INIT: u8 = 0x01;
+const PRINT: u8 = 0x02;
+const DATA: u8 = 0x04;
+const STATUS: u8 = 0x0F;
+/// Size of the printer memory.
+const BUFFER_SIZE: usize = 0x2000;
+const TILE_SIZE: usize = 16;
+const TILES_PER_ROW: usize = PAPER_WIDTH / 8;
+/// Pixel rows fed per unit of margin.
+const MARGIN_ROWS: usize = 8;
+/// Palette used when a game leaves it at 0.
+const DEFAULT_PALETTE: u8 = 0xE4;
+/// Rows of paper kept, the older ones are only in the printouts.
+const PAPER_ROWS: usize = 4096;
+/// Status inquiries answered as busy after a print.
+const PRINTING_POLLS: u8 = 3;
+
+// Bits of the status byte.
+const CHECKSUM_ERROR: u8 = 1 << 0;
+const PRINTING: u8 = 1 << 1;
+const READY: u8 = 1 << 2;
+const UNPROCESSED: u8 = 1 << 3;
+const PACKET_ERROR: u8 = 1 << 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Receiving {
+    Magic(usize),
+    Command,
+    Compression,
+    Length(usize),
+    Data,
+    Checksum(usize),
+    Alive,
+    Status,
+}
+
+/// An image printed by a single print command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Printout {
+    pub height: usize,
+    /// Shade of each pixel, from 0 (white) to 3 (black), row by row.
+    pub pixels: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Printer {
+    receiving: Receiving,
+    command: u8,
+    compressed: bool,
+    length: u16,
+    data: Vec<u8>,
+    checksum: u16,
+    status: u8,
+    printing_polls: u8,
+    /// Tile data waiting to be printed.
+    buffer: Vec<u8>,
+    /// The last `PAPER_ROWS` rows printed, `PAPER_WIDTH` shades per row.
+    paper: Vec<u8>,
+    printouts: Vec<Printout>,
+    replies: VecDeque<u8>,
+}
+impl Default for Printer {
+    fn default() -> Self {
+        Self {
+            receiving: Receiving::Magic(0),
+            command: 0,
+            compressed: false,
+            length: 0,
+            data: Vec::new(),
+            checksum: 0,
+            status: 0,
+            printing_polls: 0,
+            buffer: Vec::new(),
+            paper: Vec::new(),
+            printouts: Vec::new(),
+            replies: VecDeque::new(),
+        }
+    }
+}
+impl Printer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// The last rows printed, `PAPER_WIDTH` shades per row.
+    pub fn paper(&self) -> &[u8] {
+        &self.paper
+    }
+    /// The images printed since the last call.
+    pub fn take_printouts(&mut self) -> Vec<Printout> {
+        std::mem::take(&mut self.printouts)
+    }
+    /// Shift in `byte`, returning the byte shifted out.
+    pub fn exchange(&mut self, byte: u8) -> u8 {
+        let mut reply = 0x00;
+        self.receiving = match self.receiving {
+            Receiving::Magic(index) if byte == MAGIC[index] => match index + 1 {
+                2 => Receiving::Command,
+                next => Receiving::Magic(next),
+            },
+            Receiving::Magic(_) => Receiving::Magic(0),
+            Receiving::Command => {
+                self.command = byte;
+                self.checksum = byte as u16;
+                Receiving::Compression
+            }
+            Receiving::Compression => {
+                self.compressed = byte & 1 != 0;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                Receiving::Length(0)
+            }
+            Receiving::Length(0) => {
+                self.length = byte as u16;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                Receiving::Length(1)
+            }
+            Receiving::Length(_) => {
+                self.length |= (byte as u16) << 8;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                self.data.clear();
+                if self.length == 0 {
+                    Receiving::Checksum(0)
+                } else {
+                    Receiving::Data
+                }
+            }
+            Receiving::Data => {
+                self.data.push(byte);
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                if self.data.len() == self.length as usize {
+                    Receiving::Checksum(0)
+                } else {
+                    Receiving::Data
+                }
+            }
+            Receiving::Checksum(0) => {
+                self.checksum = self.checksum.wrapping_sub(byte as u16);
+                Receiving::Checksum(1)
+            }
+            Receiving::Checksum(_) => {
+                self.checksum = self.checksum.wrapping_sub((byte as u16) << 8);
+                Receiving::Alive
+            }
+            Receiving::Alive => {
+                self.run_command();
+                reply = DEVICE_ID;
+                Receiving::Status
+            }
+            Receiving::Status => {
+                reply = self.status;
+                Receiving::Magic(0)
+            }
+        };
+        reply
+    }
+    fn run_command(&mut self) {
+        if self.checksum != 0 {
+            self.status |= CHECKSUM_ERROR;
+            return;
+        }
+        self.status &= !CHECKSUM_ERROR;
+        match self.command {
+            INIT => {
+                self.buffer.clear();
+                self.status = 0;
+            }
+            DATA if self.data.is_empty() => self.status |= READY,
+            DATA => {
+                let data = if self.compressed {
+                    decompress(&self.data)
+                } else {
+                    std::mem::take(&mut self.data)
+                };
+                let room = BUFFER_SIZE - self.buffer.len();
+                self.buffer.extend(data.into_iter().take(room));
+                self.status |= UNPROCESSED;
+            }
+            PRINT if self.data.len() == 4 => {
+                let (sheets, margins, palette) = (self.data[0], self.data[1], self.data[2]);
+                self.print(sheets, margins, palette);
+                self.status = (self.status | PRINTING) & !(READY | UNPROCESSED);
+                self.printing_polls = PRINTING_POLLS;
+            }
+            STATUS => {
+                if self.printing_polls > 0 {
+                    self.printing_polls -= 1;
+                    if self.printing_polls == 0 {
+                        self.status &= !PRINTING;
+                    }
+                }
+            }
+            _ => self.status |= PACKET_ERROR,
+        }
+    }
+    /// Print the buffer with `palette`, feeding the margins before and
+    /// after. No sheets only feeds the paper.
+    fn print(&mut self, sheets: u8, margins: u8, palette: u8) {
+        let palette = if palette == 0 {
+            DEFAULT_PALETTE
+        } else {
+            palette
+        };
+        let mut pixels = vec![0; (margins >> 4) as usize * MARGIN_ROWS * PAPER_WIDTH];
+        if sheets > 0 {
+            let rows = self.buffer.len() / (TILE_SIZE * TILES_PER_ROW) * 8;
+            for y in 0..rows {
+                for x in 0..PAPER_WIDTH {
+                    let tile = y / 8 * TILES_PER_ROW + x / 8;
+                    let line = tile * TILE_SIZE + y % 8 * 2;
+                    let bit = 7 - x % 8;
+                    let low = self.buffer[line] >> bit & 1;
+                    let high = self.buffer[line + 1] >> bit & 1;
+                    let color = high << 1 | low;
+                    pixels.push(palette >> (color * 2) & 0b11);
+                }
+            }
+        }
+        pixels.resize(
+            pixels.len() + (margins & 0x0F) as usize * MARGIN_ROWS * PAPER_WIDTH,
+            0,
+        );
+        self.buffer.clear();
+        self.paper.extend(&pixels);
+        let excess = self.paper.len().saturating_sub(PAPER_ROWS * PAPER_WIDTH);
+        self.paper.drain(..excess);
+        self.printouts.push(Printout {
+            height: pixels.len() / PAPER_WIDTH,
+            pixels,
+        });
+    }
+}
+impl LinkCable for Printer {
+    fn send(&mut self, message: LinkMessage) {
+        // The printer always runs on the clock of the Game Boy.
+        if let LinkMessage::Transfer(byte) = message {
+            let reply = self.exchange(byte);
+            self.replies.push_back(reply);
+        }
+    }
+    fn receive(&mut self) -> Option<LinkMessage> {
+        self.replies.pop_front().map(LinkMessage::Reply)
+    }
+}
+
+/// Expand the run length encoding of the printer: a byte with bit 7 set
+/// repeats the next byte `(n & 0x7F) + 2` times, otherwise the next
+/// `n + 1` bytes are copied.
+fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    let mut bytes = data.iter();
+    while let Some(&header) = bytes.next() {
+        if header & 0x80 != 0 {
+            if let Some(&byte) = bytes.next() {
+                output.extend(std::iter::repeat_n(byte, (header & 0x7F) as usize + 2));
+            }
+        } else {
+            output.extend(bytes.by_ref().take(header as usize + 1));
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Send a packet, returning the two bytes answered after it.
+    fn send_packet(printer: &mut Printer, command: u8, compressed: bool, data: &[u8]) -> [u8; 2] {
+        let mut packet = vec![command, compressed as u8];
+        packet.extend((data.len() as u16).to_le_bytes());
+        packet.extend(data);
+        let checksum = packet
+            .iter()
+            .fold(0u16, |sum, &byte| sum.wrapping_add(byte as u16));
+        packet.extend(checksum.to_le_bytes());
+        for byte in MAGIC.iter().chain(&packet) {
+            assert_eq!(printer.exchange(*byte), 0);
+        }
+        [printer.exchange(0), printer.exchange(0)]
+    }
+
+    #[test]
+    fn check_decompress() {
+        assert_eq!(
+            decompress(&[0x81, 0xAA, 0x01, 0x01, 0x02]),
+            vec![0xAA, 0xAA, 0xAA, 0x01, 0x02]
+        );
+    }
+
+    #[test]
+    fn print_compressed_band() {
+        let mut printer = Printer::new();
+        assert_eq!(send_packet(&mut printer, INIT, false, &[]), [DEVICE_ID, 0]);
+        // A band of 20 x 2 tiles, all of color 1: low plane set, high clear.
+        let mut band = Vec::new();
+        for _ in 0..(TILES_PER_ROW * 2 * TILE_SIZE / 2) {
+            band.extend([0x01, 0xFF, 0x00]);
+        }
+        let [_, status] = send_packet(&mut printer, DATA, true, &band);
+        assert_eq!(status, UNPROCESSED);
+        let [_, status] = send_packet(&mut printer, DATA, false, &[]);
+        assert_eq!(status, UNPROCESSED | READY);
+        // One sheet, a margin after, palette mapping color 1 to black.
+        let [_, status] = send_packet(&mut printer, PRINT, false, &[1, 0x01, 0b1100, 0x40]);
+        assert_eq!(status, PRINTING);
+
+        let printouts = printer.take_printouts();
+        assert_eq!(printouts.len(), 1);
+        assert_eq!(printouts[0].height, 16 + MARGIN_ROWS);
+        assert!(printouts[0].pixels[..16 * PAPER_WIDTH]
+            .iter()
+            .all(|&shade| shade == 3));
+        assert_eq!(printer.paper().len(), (16 + MARGIN_ROWS) * PAPER_WIDTH);
+
+        let bad_checksum = [0x88, 0x33, STATUS, 0, 0, 0, 0xFF, 0xFF, 0, 0];
+        let replies = bad_checksum.map(|byte| printer.exchange(byte));
+        assert_eq!(replies[9] & CHECKSUM_ERROR, CHECKSUM_ERROR);
+    }
+
+    #[test]
+    fn keep_the_last_rows() {
+        let mut printer = Printer::new();
+        // Feeds of 30 margins of 8 rows, past the rows kept.
+        for _ in 0..PAPER_ROWS / 240 + 1 {
+            printer.print(0, 0xFF, 0);
+        }
+        assert_eq!(printer.paper().len(), PAPER_ROWS * PAPER_WIDTH);
+        assert_eq!(printer.take_printouts().len(), PAPER_ROWS / 240 + 1);
+    }
+}
@@ -0,0 +1,198 @@
+//! The real time clock of MBC3 cartridges.
+//!
+//! Registers 0x08-0x0C hold the seconds, minutes, hours, the low 8 bits
+//! of the day counter, then bit 8 of the days, the halt flag (bit 6) and
+//! the day counter carry (bit 7). The game reads a copy latched on demand.
+//!
+//! Saves append the clock in the format most emulators share: the five
+//! registers then the five latched ones as 32-bit little endian values,
+//! then the time of the save as 64-bit UNIX seconds, 48 bytes in total.
+
+use crate::{
+    apu::CPU_CLOCK,
+    state::{Snapshot, StateError, StateReader, StateWriter},
+};
+
+pub const FOOTER_SIZE: usize = 48;
+/// The same footer with a 32-bit time, written by older emulators.
+const SHORT_FOOTER_SIZE: usize = 44;
+
+pub const SECONDS: u8 = 0x08;
+pub const DAYS_HIGH: u8 = 0x0C;
+
+const HALT: u8 = 1 << 6;
+const DAY_CARRY: u8 = 1 << 7;
+const DAYS: u64 = 512;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Rtc {
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    days: u16,
+    halt: bool,
+    carry: bool,
+    latched: [u8; 5],
+    /// T-cycles into the current second.
+    cycles: u32,
+}
+impl Rtc {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    fn registers(&self) -> [u8; 5] {
+        let mut days_high = (self.days >> 8) as u8 & 1;
+        if self.halt {
+            days_high |= HALT;
+        }
+        if self.carry {
+            days_high |= DAY_CARRY;
+        }
+        [
+            self.seconds,
+            self.minutes,
+            self.hours,
+            self.days as u8,
+            days_high,
+        ]
+    }
+    fn set_registers(&mut self, registers: [u8; 5]) {
+        let [seconds, minutes, hours, days_low, days_high] = registers;
+        self.seconds = seconds & 0x3F;
+        self.minutes = minutes & 0x3F;
+        self.hours = hours & 0x1F;
+        self.days = (days_high as u16 & 1) << 8 | days_low as u16;
+        self.halt = days_high & HALT != 0;
+        self.carry = days_high & DAY_CARRY != 0;
+    }
+    /// Copy the registers to the ones the game reads.
+    pub fn latch(&mut self) {
+        self.latched = self.registers();
+    }
+    /// The latched `register`, from `SECONDS` to `DAYS_HIGH`.
+    pub fn read(&self, register: u8) -> u8 {
+        match register {
+            SECONDS..=DAYS_HIGH => self.latched[(register - SECONDS) as usize],
+            _ => 0xFF,
+        }
+    }
+    pub fn write(&mut self, register: u8, value: u8) {
+        if let SECONDS..=DAYS_HIGH = register {
+            let mut registers = self.registers();
+            registers[(register - SECONDS) as usize] = value;
+            self.set_registers(registers);
+            if register == SECONDS {
+                self.cycles = 0;
+            }
+        }
+    }
+    /// Let `seconds` go by, unless the clock is halted.
+    pub fn advance(&mut self, seconds: u64) {
+        if self.halt {
+            return;
+        }
+        let total = seconds
+            + self.seconds as u64
+            + self.minutes as u64 * 60
+            + self.hours as u64 * 3600
+            + self.days as u64 * 86400;
+        self.seconds = (total % 60) as u8;
+        self.minutes = (total / 60 % 60) as u8;
+        self.hours = (total / 3600 % 24) as u8;
+        let days = total / 86400;
+        if days >= DAYS {
+            self.carry = true;
+        }
+        self.days = (days % DAYS) as u16;
+    }
+    /// Advance the clock by `cycles` T-cycles of a console running at
+    /// `clock` T-cycles per second.
+    pub fn tick(&mut self, cycles: u32, clock: u32) {
+        if self.halt {
+            return;
+        }
+        self.cycles += cycles;
+        if self.cycles >= clock {
+            self.advance((self.cycles / clock) as u64);
+            self.cycles %= clock;
+        }
+    }
+    /// The clock as saved at `now`, in UNIX seconds.
+    pub fn footer(&self, now: u64) -> [u8; FOOTER_SIZE] {
+        let mut footer = [0; FOOTER_SIZE];
+        let values = self.registers().into_iter().chain(self.latched);
+        for (chunk, value) in footer.chunks_exact_mut(4).zip(values) {
+            chunk.copy_from_slice(&(value as u32).to_le_bytes());
+        }
+        footer[40..].copy_from_slice(&now.to_le_bytes());
+        footer
+    }
+    /// The clock saved in `footer`, with the time elapsed until `now`.
+    pub fn from_footer(footer: &[u8], now: u64) -> Option<Self> {
+        if footer.len() != FOOTER_SIZE && footer.len() != SHORT_FOOTER_SIZE {
+            return None;
+        }
+        let value = |index: usize| footer[index * 4];
+        let mut rtc = Rtc::new();
+        rtc.set_registers([value(0), value(1), value(2), value(3), value(4)]);
+        rtc.latched = [value(5), value(6), value(7), value(8), value(9)];
+        let saved = match footer[40..].try_into() {
+            Ok(time) => u64::from_le_bytes(time),
+            Err(_) => u32::from_le_bytes(footer[40..].try_into().ok()?) as u64,
+        };
+        rtc.advance(now.saturating_sub(saved));
+        Some(rtc)
+    }
+}
+
+impl Snapshot for Rtc {
+    fn save(&self, writer: &mut StateWriter) {
+        writer.bytes(&self.registers());
+        writer.bytes(&self.latched);
+        writer.u32(self.cycles);
+    }
+    fn load(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        let mut registers = [0; 5];
+        reader.bytes_into(&mut registers)?;
+        self.set_registers(registers);
+        reader.bytes_into(&mut self.latched)?;
+        self.cycles = reader.u32()? % CPU_CLOCK;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn catch_up_after_load() {
+        let mut rtc = Rtc::new();
+        rtc.write(SECONDS + 1, 59);
+        rtc.write(SECONDS + 2, 23);
+        rtc.write(DAYS_HIGH, 1);
+        rtc.write(SECONDS + 3, 0xFF);
+        rtc.tick(CPU_CLOCK * 59, CPU_CLOCK);
+        let footer = rtc.footer(1_000);
+        // Two minutes later, 511 days and 23:59:59 become 512 days.
+        let mut rtc = Rtc::from_footer(&footer, 1_120).unwrap();
+        rtc.latch();
+        assert_eq!(rtc.read(SECONDS), 59);
+        assert_eq!(rtc.read(SECONDS + 1), 1);
+        assert_eq!(rtc.read(SECONDS + 2), 0);
+        assert_eq!(rtc.read(SECONDS + 3), 0);
+        assert_eq!(rtc.read(DAYS_HIGH), DAY_CARRY);
+    }
+
+    #[test]
+    fn tick_at_model_clock() {
+        let sgb = crate::model::Model::Sgb.clock();
+        let mut rtc = Rtc::new();
+        rtc.tick(CPU_CLOCK, sgb);
+        rtc.latch();
+        assert_eq!(rtc.read(SECONDS), 0);
+        rtc.tick(sgb - CPU_CLOCK, sgb);
+        rtc.latch();
+        assert_eq!(rtc.read(SECONDS), 1);
+    }
+}
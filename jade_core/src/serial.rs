@@ -0,0 +1,373 @@
+//! The serial port: SB (0xFF01) and SC (0xFF02).
+//!
+//! A transfer shifts the 8 bits of SB out while the bits of the other Game
+//! Boy shift in. The side using the internal clock drives the transfer at
+//! 8192 Hz (262144 Hz in CGB fast mode), the side on the external clock
+//! completes its transfer whenever the other side clocks one.
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+pub const SB: u16 = 0xFF01;
+pub const SC: u16 = 0xFF02;
+/// Bit of the serial interrupt in IE and IF.
+pub const SERIAL_INTERRUPT: u8 = 1 << 3;
+
+const TRANSFER_START: u8 = 0x80;
+const INTERNAL_CLOCK: u8 = 0x01;
+const FAST_CLOCK: u8 = 0x02;
+/// T-cycles to shift a byte at 8192 Hz.
+const TRANSFER_CYCLES: u32 = 8 * 512;
+/// T-cycles to shift a byte at 262144 Hz.
+const FAST_TRANSFER_CYCLES: u32 = 8 * 16;
+
+/// What travels over the link cable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkMessage {
+    /// The sender clocked a transfer of this byte, the receiver must answer
+    /// with a `Reply` of its own SB.
+    Transfer(u8),
+    Reply(u8),
+}
+
+/// The other end of the link cable.
+pub trait LinkCable: Send {
+    fn send(&mut self, message: LinkMessage);
+    /// Next message from the other end, if any arrived.
+    fn receive(&mut self) -> Option<LinkMessage>;
+    /// Whether the other end is gone for good, no reply will come.
+    fn is_closed(&self) -> bool {
+        false
+    }
+}
+
+/// A device shared with the rest of the program, to look at its state
+/// while it is plugged.
+impl<T: LinkCable> LinkCable for Arc<Mutex<T>> {
+    fn send(&mut self, message: LinkMessage) {
+        if let Ok(mut device) = self.lock() {
+            device.send(message);
+        }
+    }
+    fn receive(&mut self) -> Option<LinkMessage> {
+        self.lock().ok()?.receive()
+    }
+    fn is_closed(&self) -> bool {
+        self.lock().is_ok_and(|device| device.is_closed())
+    }
+}
+
+type Wire<M> = Arc<Mutex<VecDeque<M>>>;
+
+/// One end of a link cable between two consoles of the same process.
+///
+/// Messages are queued until the other console ticks, so two consoles
+/// clocked in a fixed order always see the same exchanges.
+#[derive(Debug)]
+pub struct WireLink<M = LinkMessage> {
+    outgoing: Wire<M>,
+    incoming: Wire<M>,
+}
+impl<M> WireLink<M> {
+    /// Both ends of a wire.
+    pub fn pair() -> (Self, Self) {
+        let (a, b) = (Wire::default(), Wire::default());
+        (
+            WireLink {
+                outgoing: a.clone(),
+                incoming: b.clone(),
+            },
+            WireLink {
+                outgoing: b,
+                incoming: a,
+            },
+        )
+    }
+    pub(crate) fn push(&mut self, message: M) {
+        if let Ok(mut outgoing) = self.outgoing.lock() {
+            outgoing.push_back(message);
+        }
+    }
+    pub(crate) fn pop(&mut self) -> Option<M> {
+        self.incoming.lock().ok()?.pop_front()
+    }
+}
+impl LinkCable for WireLink {
+    fn send(&mut self, message: LinkMessage) {
+        self.push(message);
+    }
+    fn receive(&mut self) -> Option<LinkMessage> {
+        self.pop()
+    }
+}
+
+/// Both ends of an in-process link cable.
+pub fn cable() -> (WireLink, WireLink) {
+    WireLink::pair()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TransferState {
+    Idle,
+    /// Shifting with the internal clock, `remaining` T-cycles to go.
+    Clocking {
+        remaining: u32,
+    },
+    /// Done shifting, waiting for the reply of the other end.
+    WaitingReply,
+}
+
+pub struct Serial {
+    cgb: bool,
+    data: u8,
+    control: u8,
+    state: TransferState,
+    reply: Option<u8>,
+    interrupt: bool,
+    link: Option<Box<dyn LinkCable>>,
+}
+impl Default for Serial {
+    fn default() -> Self {
+        Self {
+            cgb: false,
+            data: 0,
+            control: 0,
+            state: TransferState::Idle,
+            reply: None,
+            interrupt: false,
+            link: None,
+        }
+    }
+}
+impl std::fmt::Debug for Serial {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Serial")
+            .field("data", &self.data)
+            .field("control", &self.control)
+            .field("state", &self.state)
+            .field("connected", &self.link.is_some())
+            .finish()
+    }
+}
+impl Serial {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// A serial port supporting the CGB fast clock.
+    pub fn new_cgb() -> Self {
+        Self {
+            cgb: true,
+            ..Default::default()
+        }
+    }
+    /// Plug the link cable, replacing the previous one.
+    pub fn connect(&mut self, link: Box<dyn LinkCable>) {
+        self.link = Some(link);
+    }
+    pub fn disconnect(&mut self) -> Option<Box<dyn LinkCable>> {
+        self.link.take()
+    }
+    pub fn is_connected(&self) -> bool {
+        self.link.is_some()
+    }
+    pub fn read(&self, address: u16) -> u8 {
+        match address {
+            SB => self.data,
+            SC if self.cgb => self.control | 0x7C,
+            SC => self.control | 0x7E,
+            _ => 0xFF,
+        }
+    }
+    pub fn write(&mut self, address: u16, value: u8) {
+        match address {
+            SB => self.data = value,
+            SC => {
+                let mask = if self.cgb { 0x83 } else { 0x81 };
+                self.control = value & mask;
+                if self.control & (TRANSFER_START | INTERNAL_CLOCK)
+                    == TRANSFER_START | INTERNAL_CLOCK
+                {
+                    self.start_transfer();
+                }
+            }
+            _ => {}
+        }
+    }
+    fn start_transfer(&mut self) {
+        let remaining = if self.control & FAST_CLOCK != 0 {
+            FAST_TRANSFER_CYCLES
+        } else {
+            TRANSFER_CYCLES
+        };
+        self.state = TransferState::Clocking { remaining };
+        self.reply = None;
+        if let Some(link) = &mut self.link {
+            link.send(LinkMessage::Transfer(self.data));
+        }
+    }
+    fn finish_transfer(&mut self, received: u8) {
+        self.data = received;
+        self.control &= !TRANSFER_START;
+        self.state = TransferState::Idle;
+        self.interrupt = true;
+    }
+    /// Handle the messages from the other end of the cable.
+    fn receive(&mut self) {
+        while let Some(message) = self.link.as_mut().and_then(|link| link.receive()) {
+            match message {
+                LinkMessage::Transfer(byte) => {
+                    // Only a transfer armed on the external clock shifts,
+                    // otherwise the other end reads all ones.
+                    let armed = self.control & (TRANSFER_START | INTERNAL_CLOCK) == TRANSFER_START;
+                    let reply = if armed { self.data } else { 0xFF };
+                    if let Some(link) = &mut self.link {
+                        link.send(LinkMessage::Reply(reply));
+                    }
+                    if armed {
+                        self.finish_transfer(byte);
+                    }
+                }
+                LinkMessage::Reply(byte) => self.reply = Some(byte),
+            }
+        }
+    }
+    /// Advance the transfer by `cycles` T-cycles.
+    pub fn tick(&mut self, cycles: u32) {
+        self.receive();
+        if let TransferState::Clocking { remaining } = self.state {
+            self.state = match remaining.checked_sub(cycles) {
+                Some(remaining) if remaining > 0 => TransferState::Clocking { remaining },
+                _ => TransferState::WaitingReply,
+            };
+        }
+        if self.state == TransferState::WaitingReply {
+            // Without a cable the other end reads as all ones.
+            match (self.reply.take(), &self.link) {
+                (Some(byte), _) => self.finish_transfer(byte),
+                (None, None) => self.finish_transfer(0xFF),
+                (None, Some(link)) if link.is_closed() => self.finish_transfer(0xFF),
+                (None, Some(_)) => {}
+            }
+        }
+    }
+    /// Whether the serial interrupt was requested since the last call.
+    pub fn take_interrupt(&mut self) -> bool {
+        std::mem::take(&mut self.interrupt)
+    }
+}
+
+/// The link cable stays plugged as it is.
+impl Snapshot for Serial {
+    fn save(&self, writer: &mut StateWriter) {
+        writer.u8(self.data);
+        writer.u8(self.control);
+        match self.state {
+            TransferState::Idle => writer.u8(0),
+            TransferState::Clocking { remaining } => {
+                writer.u8(1);
+                writer.u32(remaining);
+            }
+            TransferState::WaitingReply => writer.u8(2),
+        }
+        writer.bool(self.reply.is_some());
+        writer.u8(self.reply.unwrap_or_default());
+        writer.bool(self.interrupt);
+    }
+    fn load(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.data = reader.u8()?;
+        self.control = reader.u8()?;
+        self.state = match reader.u8()? {
+            1 => TransferState::Clocking {
+                remaining: reader.u32()?,
+            },
+            2 => TransferState::WaitingReply,
+            _ => TransferState::Idle,
+        };
+        let has_reply = reader.bool()?;
+        let reply = reader.u8()?;
+        self.reply = has_reply.then_some(reply);
+        self.interrupt = reader.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transfer_without_cable() {
+        let mut serial = Serial::new();
+        serial.write(SB, 0x42);
+        serial.write(SC, 0x81);
+        assert_eq!(serial.read(SC), 0xFF);
+        serial.tick(TRANSFER_CYCLES - 1);
+        assert!(!serial.take_interrupt());
+        serial.tick(1);
+        assert!(serial.take_interrupt());
+        assert_eq!(serial.read(SB), 0xFF);
+        assert_eq!(serial.read(SC), 0x7F);
+    }
+
+    #[test]
+    fn exchange_bytes() {
+        let (a, b) = cable();
+        let mut master = Serial::new();
+        master.connect(Box::new(a));
+        let mut slave = Serial::new();
+        slave.connect(Box::new(b));
+        slave.write(SB, 0x22);
+        slave.write(SC, 0x80);
+        master.write(SB, 0x11);
+        master.write(SC, 0x81);
+
+        master.tick(TRANSFER_CYCLES);
+        // Still waiting for the reply.
+        assert!(!master.take_interrupt());
+        slave.tick(4);
+        assert!(slave.take_interrupt());
+        assert_eq!(slave.read(SB), 0x11);
+        master.tick(4);
+        assert!(master.take_interrupt());
+        assert_eq!(master.read(SB), 0x22);
+
+        // A slave that did not arm a transfer keeps its byte.
+        slave.write(SB, 0x33);
+        slave.write(SC, 0x00);
+        master.write(SC, 0x81);
+        master.tick(TRANSFER_CYCLES);
+        slave.tick(4);
+        assert!(!slave.take_interrupt());
+        assert_eq!(slave.read(SB), 0x33);
+        master.tick(4);
+        assert!(master.take_interrupt());
+        assert_eq!(master.read(SB), 0xFF);
+    }
+
+    #[derive(Debug)]
+    struct Unplugged;
+    impl LinkCable for Unplugged {
+        fn send(&mut self, _message: LinkMessage) {}
+        fn receive(&mut self) -> Option<LinkMessage> {
+            None
+        }
+        fn is_closed(&self) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn other_end_gone() {
+        let mut serial = Serial::new();
+        serial.connect(Box::new(Unplugged));
+        serial.write(SB, 0x42);
+        serial.write(SC, 0x81);
+        serial.tick(TRANSFER_CYCLES);
+        assert!(serial.take_interrupt());
+        assert_eq!(serial.read(SB), 0xFF);
+    }
+}
@@ -0,0 +1,542 @@
+//! The Super Game Boy.
+//!
+//! Games send commands to the SNES side by pulsing P14 and P15: both low
+//! starts a packet, then each bit is P14 low for a 0 or P15 low for a 1,
+//! followed by both high. A packet is 128 bits and a 0 stop bit; the first
+//! byte of a command holds its code times 8 plus its number of packets.
+//!
+//! Commands ending in `_TRN` copy 4 KiB from the tiles shown on the next
+//! frame, read back from the LCD output.
+
+/// Size of the SGB output, border included.
+pub const SGB_WIDTH: usize = 256;
+pub const SGB_HEIGHT: usize = 224;
+/// Size of the Game Boy screen.
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+
+/// Position of the Game Boy screen inside the border.
+const SCREEN_X: usize = 48;
+const SCREEN_Y: usize = 40;
+const PACKET_SIZE: usize = 16;
+const TILE_COLUMNS: usize = SCREEN_WIDTH / 8;
+const TILE_ROWS: usize = SCREEN_HEIGHT / 8;
+const TRANSFER_SIZE: usize = 0x1000;
+const BORDER_COLUMNS: usize = SGB_WIDTH / 8;
+const BORDER_ROWS: usize = SGB_HEIGHT / 8;
+/// Bytes of an SNES 4 bits per pixel tile.
+const BORDER_TILE_SIZE: usize = 32;
+/// Palettes of the SNES memory, loaded by PAL_TRN.
+const SYSTEM_PALETTES: usize = 512;
+/// Attribute files loaded by ATTR_TRN, 2 bits per tile.
+const ATTRIBUTE_FILES: usize = 45;
+const ATTRIBUTE_FILE_SIZE: usize = TILE_COLUMNS * TILE_ROWS / 4;
+/// Grays until the game sets its palettes, in RGB555.
+const DEFAULT_PALETTE: [u16; 4] = [0x7FFF, 0x56B5, 0x294A, 0x0000];
+
+// Command codes.
+const PAL01: u8 = 0x00;
+const PAL23: u8 = 0x01;
+const PAL03: u8 = 0x02;
+const PAL12: u8 = 0x03;
+const ATTR_BLK: u8 = 0x04;
+const ATTR_LIN: u8 = 0x05;
+const ATTR_DIV: u8 = 0x06;
+const ATTR_CHR: u8 = 0x07;
+const PAL_SET: u8 = 0x0A;
+const PAL_TRN: u8 = 0x0B;
+const MLT_REQ: u8 = 0x11;
+const CHR_TRN: u8 = 0x13;
+const PCT_TRN: u8 = 0x14;
+const ATTR_TRN: u8 = 0x15;
+const ATTR_SET: u8 = 0x16;
+const MASK_EN: u8 = 0x17;
+
+/// An RGB color of the output.
+pub type Rgb = (u8, u8, u8);
+
+fn rgb(color: u16) -> Rgb {
+    let expand = |channel: u16| {
+        let channel = (channel & 0x1F) as u8;
+        channel << 3 | channel >> 2
+    };
+    (expand(color), expand(color >> 5), expand(color >> 10))
+}
+
+/// How the Game Boy screen is hidden by MASK_EN.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Mask {
+    #[default]
+    None,
+    /// Keep showing the last frame.
+    Freeze,
+    Black,
+    /// Fill with color 0.
+    Color0,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Transfer {
+    Palettes,
+    BorderTiles { bank: usize },
+    BorderMap,
+    Attributes,
+}
+
+/// Receives the bits pulsed on P14 and P15.
+#[derive(Debug, Clone, Default)]
+struct PacketReceiver {
+    receiving: bool,
+    /// Bit set by the last pulse, stored once both lines go back high.
+    bit: Option<bool>,
+    bits: usize,
+    packet: [u8; PACKET_SIZE],
+}
+impl PacketReceiver {
+    /// Handle a write to P1, returning the packet once its stop bit arrives.
+    fn write(&mut self, value: u8) -> Option<[u8; PACKET_SIZE]> {
+        match value & 0x30 {
+            0x00 => {
+                *self = Self {
+                    receiving: true,
+                    ..Default::default()
+                };
+            }
+            0x10 if self.receiving => self.bit = Some(true),
+            0x20 if self.receiving => self.bit = Some(false),
+            0x30 => {
+                let bit = self.bit.take()?;
+                if self.bits == PACKET_SIZE * 8 {
+                    self.receiving = false;
+                    return (!bit).then_some(self.packet);
+                }
+                if bit {
+                    self.packet[self.bits / 8] |= 1 << (self.bits % 8);
+                }
+                self.bits += 1;
+            }
+            _ => {}
+        }
+        None
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Sgb {
+    receiver: PacketReceiver,
+    /// Packets of the command being received.
+    command: Vec<u8>,
+    /// Palettes 0 to 3 of the Game Boy screen, color 0 is shared.
+    palettes: [[u16; 4]; 4],
+    system_palettes: Vec<[u16; 4]>,
+    /// Palette of each tile of the Game Boy screen.
+    attributes: [u8; TILE_COLUMNS * TILE_ROWS],
+    attribute_files: Vec<u8>,
+    border_tiles: Vec<u8>,
+    /// Tile, palette and flips of each tile of the border.
+    border_map: [u16; BORDER_COLUMNS * BORDER_ROWS],
+    /// SNES palettes 4 to 7, used by the border.
+    border_palettes: [[u16; 16]; 4],
+    mask: Mask,
+    transfer: Option<Transfer>,
+    players: usize,
+    /// Color index of each pixel of the last frame shown.
+    screen: Vec<u8>,
+}
+impl Default for Sgb {
+    fn default() -> Self {
+        Self {
+            receiver: PacketReceiver::default(),
+            command: Vec::new(),
+            palettes: [DEFAULT_PALETTE; 4],
+            system_palettes: vec![DEFAULT_PALETTE; SYSTEM_PALETTES],
+            attributes: [0; TILE_COLUMNS * TILE_ROWS],
+            attribute_files: vec![0; ATTRIBUTE_FILES * ATTRIBUTE_FILE_SIZE],
+            border_tiles: vec![0; 256 * BORDER_TILE_SIZE],
+            border_map: [0; BORDER_COLUMNS * BORDER_ROWS],
+            border_palettes: [[0; 16]; 4],
+            mask: Mask::None,
+            transfer: None,
+            players: 1,
+            screen: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+        }
+    }
+}
+impl Sgb {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn mask(&self) -> Mask {
+        self.mask
+    }
+    /// Number of joypads requested by MLT_REQ.
+    pub fn players(&self) -> usize {
+        self.players
+    }
+    /// Handle a write of the game to P1.
+    pub fn write_p1(&mut self, value: u8) {
+        let Some(packet) = self.receiver.write(value) else {
+            return;
+        };
+        if self.command.is_empty() && packet[0] & 0x07 == 0 {
+            // Commands are at least one packet long.
+            return;
+        }
+        self.command.extend(packet);
+        let length = (self.command[0] & 0x07) as usize;
+        if self.command.len() >= length * PACKET_SIZE {
+            let command = std::mem::take(&mut self.command);
+            self.run(&command);
+        }
+    }
+    fn run(&mut self, data: &[u8]) {
+        let color = |index: usize| u16::from_le_bytes([data[index], data[index + 1]]);
+        match data[0] >> 3 {
+            code @ (PAL01 | PAL23 | PAL03 | PAL12) => {
+                let (first, second) = match code {
+                    PAL01 => (0, 1),
+                    PAL23 => (2, 3),
+                    PAL03 => (0, 3),
+                    _ => (1, 2),
+                };
+                for palette in &mut self.palettes {
+                    palette[0] = color(1);
+                }
+                for index in 1..4 {
+                    self.palettes[first][index] = color(1 + index * 2);
+                    self.palettes[second][index] = color(7 + index * 2);
+                }
+            }
+            ATTR_BLK => self.attribute_blocks(data),
+            ATTR_LIN => {
+                for &line in data[2..].iter().take(data[1] as usize) {
+                    let (position, palette) = ((line & 0x1F) as usize, (line >> 5) & 0b11);
+                    for row in 0..TILE_ROWS {
+                        for column in 0..TILE_COLUMNS {
+                            let on_line = if line & 0x80 != 0 {
+                                row == position
+                            } else {
+                                column == position
+                            };
+                            if on_line {
+                                self.attributes[row * TILE_COLUMNS + column] = palette;
+                            }
+                        }
+                    }
+                }
+            }
+            ATTR_DIV => {
+                let settings = data[1];
+                let position = data[2] as usize;
+                for row in 0..TILE_ROWS {
+                    for column in 0..TILE_COLUMNS {
+                        let coordinate = if settings & 0x40 != 0 { row } else { column };
+                        let palette = match coordinate.cmp(&position) {
+                            std::cmp::Ordering::Less => settings >> 2,
+                            std::cmp::Ordering::Equal => settings >> 4,
+                            std::cmp::Ordering::Greater => settings,
+                        };
+                        self.attributes[row * TILE_COLUMNS + column] = palette & 0b11;
+                    }
+                }
+            }
+            ATTR_CHR => {
+                let (mut column, mut row) = (data[1] as usize, data[2] as usize);
+                let count = u16::from_le_bytes([data[3], data[4]]) as usize;
+                let vertical = data[5] != 0;
+                for index in 0..count.min(TILE_COLUMNS * TILE_ROWS) {
+                    let Some(&byte) = data.get(6 + index / 4) else {
+                        break;
+                    };
+                    if column < TILE_COLUMNS && row < TILE_ROWS {
+                        self.attributes[row * TILE_COLUMNS + column] =
+                            byte >> (6 - index % 4 * 2) & 0b11;
+                    }
+                    if vertical {
+                        row += 1;
+                        if row == TILE_ROWS {
+                            (row, column) = (0, column + 1);
+                        }
+                    } else {
+                        column += 1;
+                        if column == TILE_COLUMNS {
+                            (column, row) = (0, row + 1);
+                        }
+                    }
+                }
+            }
+            PAL_SET => {
+                for palette in 0..4 {
+                    let index = color(1 + palette * 2) as usize % SYSTEM_PALETTES;
+                    self.palettes[palette] = self.system_palettes[index];
+                }
+                let color_0 = self.palettes[0][0];
+                for palette in &mut self.palettes {
+                    palette[0] = color_0;
+                }
+                let settings = data[9];
+                if settings & 0x80 != 0 {
+                    self.set_attribute_file(settings as usize & 0x3F);
+                }
+                if settings & 0x40 != 0 {
+                    self.mask = Mask::None;
+                }
+            }
+            PAL_TRN => self.transfer = Some(Transfer::Palettes),
+            MLT_REQ => {
+                self.players = match data[1] & 0b11 {
+                    1 => 2,
+                    3 => 4,
+                    _ => 1,
+                }
+            }
+            CHR_TRN => {
+                self.transfer = Some(Transfer::BorderTiles {
+                    bank: (data[1] & 1) as usize,
+                })
+            }
+            PCT_TRN => self.transfer = Some(Transfer::BorderMap),
+            ATTR_TRN => self.transfer = Some(Transfer::Attributes),
+            ATTR_SET => {
+                self.set_attribute_file(data[1] as usize & 0x3F);
+                if data[1] & 0x40 != 0 {
+                    self.mask = Mask::None;
+                }
+            }
+            MASK_EN => {
+                self.mask = match data[1] & 0b11 {
+                    0 => Mask::None,
+                    1 => Mask::Freeze,
+                    2 => Mask::Black,
+                    _ => Mask::Color0,
+                }
+            }
+            _ => {}
+        }
+    }
+    fn attribute_blocks(&mut self, data: &[u8]) {
+        for block in data[2..].chunks_exact(6).take(data[1] as usize) {
+            let (control, palettes) = (block[0], block[1]);
+            let (left, top) = (block[2] as usize, block[3] as usize);
+            let (right, bottom) = (block[4] as usize, block[5] as usize);
+            let inside = control & 1 != 0;
+            let border = control & 2 != 0;
+            let outside = control & 4 != 0;
+            let inside_palette = palettes & 0b11;
+            let outside_palette = palettes >> 4 & 0b11;
+            // With only the inside or outside changed, the border follows it.
+            let border_palette = match (inside, border, outside) {
+                (true, false, false) => Some(inside_palette),
+                (false, false, true) => Some(outside_palette),
+                (_, true, _) => Some(palettes >> 2 & 0b11),
+                _ => None,
+            };
+            for row in 0..TILE_ROWS {
+                for column in 0..TILE_COLUMNS {
+                    let within = (left..=right).contains(&column) && (top..=bottom).contains(&row);
+                    let on_border = within
+                        && (column == left || column == right || row == top || row == bottom);
+                    let palette = if on_border {
+                        border_palette
+                    } else if within {
+                        inside.then_some(inside_palette)
+                    } else {
+                        outside.then_some(outside_palette)
+                    };
+                    if let Some(palette) = palette {
+                        self.attributes[row * TILE_COLUMNS + column] = palette;
+                    }
+                }
+            }
+        }
+    }
+    fn set_attribute_file(&mut self, file: usize) {
+        if file >= ATTRIBUTE_FILES {
+            return;
+        }
+        let bytes = &self.attribute_files[file * ATTRIBUTE_FILE_SIZE..][..ATTRIBUTE_FILE_SIZE];
+        for (tile, attribute) in self.attributes.iter_mut().enumerate() {
+            *attribute = bytes[tile / 4] >> (6 - tile % 4 * 2) & 0b11;
+        }
+    }
+    /// Show a frame of the Game Boy, the color index of each pixel from 0
+    /// to 3, running the pending `_TRN` command with it.
+    pub fn frame(&mut self, screen: &[u8]) {
+        if screen.len() != SCREEN_WIDTH * SCREEN_HEIGHT {
+            return;
+        }
+        if let Some(transfer) = self.transfer.take() {
+            self.transfer_from(transfer, screen);
+        }
+        if self.mask != Mask::Freeze {
+            self.screen.copy_from_slice(screen);
+        }
+    }
+    fn transfer_from(&mut self, transfer: Transfer, screen: &[u8]) {
+        let data = tile_data(screen);
+        match transfer {
+            Transfer::Palettes => {
+                for (palette, colors) in self.system_palettes.iter_mut().zip(data.chunks(8)) {
+                    for (color, bytes) in palette.iter_mut().zip(colors.chunks(2)) {
+                        *color = u16::from_le_bytes([bytes[0], bytes[1]]);
+                    }
+                }
+            }
+            Transfer::BorderTiles { bank } => {
+                self.border_tiles[bank * TRANSFER_SIZE..][..TRANSFER_SIZE].copy_from_slice(&data);
+            }
+            Transfer::BorderMap => {
+                for (entry, bytes) in self.border_map.iter_mut().zip(data.chunks(2)) {
+                    *entry = u16::from_le_bytes([bytes[0], bytes[1]]);
+                }
+                let colors = &data[0x800..];
+                for (index, bytes) in colors.chunks(2).take(4 * 16).enumerate() {
+                    self.border_palettes[index / 16][index % 16] =
+                        u16::from_le_bytes([bytes[0], bytes[1]]);
+                }
+            }
+            Transfer::Attributes => {
+                let size = self.attribute_files.len();
+                self.attribute_files.copy_from_slice(&data[..size]);
+            }
+        }
+    }
+    /// The picture sent to the TV, `SGB_WIDTH` by `SGB_HEIGHT`: the Game Boy
+    /// screen colorized by the palettes, inside the border.
+    pub fn output(&self) -> Vec<Rgb> {
+        let backdrop = rgb(self.palettes[0][0]);
+        let mut output = vec![backdrop; SGB_WIDTH * SGB_HEIGHT];
+        for y in 0..SCREEN_HEIGHT {
+            for x in 0..SCREEN_WIDTH {
+                let color = match self.mask {
+                    Mask::Black => (0, 0, 0),
+                    Mask::Color0 => backdrop,
+                    Mask::None | Mask::Freeze => {
+                        let palette = self.attributes[y / 8 * TILE_COLUMNS + x / 8] as usize;
+                        let index = self.screen[y * SCREEN_WIDTH + x] as usize & 0b11;
+                        rgb(self.palettes[palette][index])
+                    }
+                };
+                output[(y + SCREEN_Y) * SGB_WIDTH + x + SCREEN_X] = color;
+            }
+        }
+        for (tile, &entry) in self.border_map.iter().enumerate() {
+            let (column, row) = (tile % BORDER_COLUMNS, tile / BORDER_COLUMNS);
+            let data = &self.border_tiles[(entry & 0xFF) as usize * BORDER_TILE_SIZE..];
+            let palette = &self.border_palettes[(entry >> 10 & 0b11) as usize];
+            let (flip_x, flip_y) = (entry & 0x4000 != 0, entry & 0x8000 != 0);
+            for y in 0..8 {
+                let line = if flip_y { 7 - y } else { y };
+                let planes = [
+                    data[line * 2],
+                    data[line * 2 + 1],
+                    data[16 + line * 2],
+                    data[17 + line * 2],
+                ];
+                for x in 0..8 {
+                    let bit = if flip_x { x } else { 7 - x };
+                    let index = planes
+                        .iter()
+                        .enumerate()
+                        .fold(0, |index, (plane, byte)| index | (byte >> bit & 1) << plane);
+                    // Color 0 of the border is transparent.
+                    if index != 0 {
+                        output[(row * 8 + y) * SGB_WIDTH + column * 8 + x] =
+                            rgb(palette[index as usize]);
+                    }
+                }
+            }
+        }
+        output
+    }
+}
+
+/// Read back the tiles shown by the LCD: 256 tiles of 16 bytes, 20 per
+/// row, from the color index of each pixel.
+fn tile_data(screen: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(TRANSFER_SIZE);
+    for tile in 0..TRANSFER_SIZE / 16 {
+        let (column, row) = (tile % TILE_COLUMNS, tile / TILE_COLUMNS);
+        for y in 0..8 {
+            let (mut low, mut high) = (0, 0);
+            for x in 0..8 {
+                let index = screen[(row * 8 + y) * SCREEN_WIDTH + column * 8 + x];
+                low |= (index & 1) << (7 - x);
+                high |= (index >> 1 & 1) << (7 - x);
+            }
+            data.extend([low, high]);
+        }
+    }
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pulse the packets of a command on P1.
+    fn send_command(sgb: &mut Sgb, data: &[u8]) {
+        for packet in data.chunks(PACKET_SIZE) {
+            sgb.write_p1(0x00);
+            sgb.write_p1(0x30);
+            let bits = (0..128).map(|bit| {
+                packet
+                    .get(bit / 8)
+                    .is_some_and(|byte| byte >> (bit % 8) & 1 != 0)
+            });
+            for bit in bits.chain([false]) {
+                sgb.write_p1(if bit { 0x10 } else { 0x20 });
+                sgb.write_p1(0x30);
+            }
+        }
+    }
+
+    #[test]
+    fn colorize_with_palettes_and_attributes() {
+        let mut sgb = Sgb::new();
+        // PAL01: color 0 then colors 1-3 of palettes 0 and 1, pure red for
+        // color 3 of palette 1.
+        let mut pal01 = [0; PACKET_SIZE];
+        pal01[0] = PAL01 << 3 | 1;
+        pal01[13..15].copy_from_slice(&0x001Fu16.to_le_bytes());
+        send_command(&mut sgb, &pal01);
+        // ATTR_BLK: palette 1 inside the top left tile only.
+        let attr_blk = [ATTR_BLK << 3 | 1, 1, 0b001, 0b01, 0, 0, 0, 0];
+        send_command(&mut sgb, &attr_blk);
+
+        sgb.frame(&[3; SCREEN_WIDTH * SCREEN_HEIGHT]);
+        let output = sgb.output();
+        let at = |x: usize, y: usize| output[(y + SCREEN_Y) * SGB_WIDTH + x + SCREEN_X];
+        assert_eq!(at(0, 0), (0xFF, 0, 0));
+        assert_eq!(at(8, 0), (0, 0, 0));
+        assert_eq!(output[0], (0, 0, 0));
+    }
+
+    #[test]
+    fn border_from_transfers() {
+        let mut sgb = Sgb::new();
+        // Tile 0 is shown with all pixels of color 1.
+        let mut screen = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT];
+        for y in 0..8 {
+            for x in 0..8 {
+                screen[y * SCREEN_WIDTH + x] = 1;
+            }
+        }
+        send_command(&mut sgb, &[CHR_TRN << 3 | 1, 0]);
+        sgb.frame(&screen);
+        // All map entries are 0: tile 0 with the first border palette,
+        // whose color 1 is white.
+        let mut screen = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT];
+        let tile = 0x800 / 16;
+        let (column, row) = (tile % TILE_COLUMNS, tile / TILE_COLUMNS);
+        for (x, index) in [1, 3, 3, 3, 3, 3, 3, 3].into_iter().enumerate() {
+            screen[(row * 8 + 1) * SCREEN_WIDTH + column * 8 + x] = index;
+        }
+        send_command(&mut sgb, &[PCT_TRN << 3 | 1]);
+        sgb.frame(&screen);
+
+        let output = sgb.output();
+        assert_eq!(output[0], (0xFF, 0xFF, 0xFF));
+        assert_eq!(output[SGB_WIDTH * SGB_HEIGHT - 1], (0xFF, 0xFF, 0xFF));
+    }
+}
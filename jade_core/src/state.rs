@@ -0,0 +1,452 @@
+//! Save states: a snapshot of a console in a versioned binary format.
+//!
+//! A state starts with a header holding the format version, the CRC-32 of
+//! the ROM it was made with, the time it was saved, a 160 x 144
+//! thumbnail at 2 bits per pixel and, since version 2, a name given by the
+//! player. Each component follows in a section
+//! made of a 4 byte tag and a 32-bit length, all numbers little endian.
+//!
+//! Loading skips sections it does not know, and leaves components whose
+//! section is missing as they are. Fields are only ever appended to a
+//! section and read as their default value when absent, so a state from
+//! an older version loads as is; `migrate` handles the changes that
+//! cannot be made that way.
+
+use std::fmt::Display;
+
+use crate::sgb::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+pub const STATE_VERSION: u16 = 2;
+/// First version whose header holds a name.
+const NAMED_VERSION: u16 = 2;
+
+const MAGIC: &[u8; 8] = b"JADESTAT";
+const THUMBNAIL_SIZE: usize = SCREEN_WIDTH * SCREEN_HEIGHT / 4;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateError {
+    BadMagic,
+    /// Made by a newer version of the emulator.
+    UnsupportedVersion(u16),
+    /// Made with another ROM, whose CRC-32 is given.
+    WrongRom(u32),
+    Truncated,
+}
+impl Display for StateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StateError::BadMagic => write!(f, "not a save state"),
+            StateError::UnsupportedVersion(version) => {
+                write!(f, "unsupported save state version {version}")
+            }
+            StateError::WrongRom(crc) => {
+                write!(f, "save state made with another ROM (CRC-32 {crc:08X})")
+            }
+            StateError::Truncated => write!(f, "save state truncated"),
+        }
+    }
+}
+impl std::error::Error for StateError {}
+
+/// A component which can be saved in and restored from a state.
+pub trait Snapshot {
+    fn save(&self, writer: &mut StateWriter);
+    /// Restore what `save` wrote. Only the component changes, since a
+    /// later section failing puts back what it held before.
+    fn load(&mut self, reader: &mut StateReader) -> Result<(), StateError>;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StateHeader {
+    pub version: u16,
+    pub rom_crc: u32,
+    /// UNIX time of the save, in seconds.
+    pub saved_at: u64,
+    /// Color index of each pixel of the screen, from 0 for white to 3.
+    pub thumbnail: Vec<u8>,
+    /// Empty unless the player named the state.
+    pub name: String,
+}
+impl StateHeader {
+    pub fn new(rom_crc: u32, saved_at: u64, thumbnail: &[u8]) -> Self {
+        Self {
+            version: STATE_VERSION,
+            rom_crc,
+            saved_at,
+            thumbnail: thumbnail.to_vec(),
+            name: String::new(),
+        }
+    }
+    /// Read the header at the start of `state`, the rest is left unread.
+    pub fn parse(state: &[u8]) -> Result<Self, StateError> {
+        Self::read(&mut StateReader::new(state))
+    }
+    fn read(reader: &mut StateReader) -> Result<Self, StateError> {
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(StateError::BadMagic);
+        }
+        let version = reader.u16()?;
+        if version > STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        let rom_crc = reader.u32()?;
+        let saved_at = reader.u64()?;
+        let thumbnail = reader
+            .take(THUMBNAIL_SIZE)?
+            .iter()
+            .flat_map(|&byte| (0..4).map(move |pixel| byte >> (6 - pixel * 2) & 3))
+            .collect();
+        let name = if version >= NAMED_VERSION {
+            String::from_utf8_lossy(reader.bytes()?).into_owned()
+        } else {
+            String::new()
+        };
+        Ok(Self {
+            version,
+            rom_crc,
+            saved_at,
+            thumbnail,
+            name,
+        })
+    }
+    fn write(&self, writer: &mut StateWriter) {
+        writer.bytes.extend_from_slice(MAGIC);
+        writer.u16(self.version);
+        writer.u32(self.rom_crc);
+        writer.u64(self.saved_at);
+        let mut thumbnail = [0; THUMBNAIL_SIZE];
+        for (byte, pixels) in thumbnail.iter_mut().zip(self.thumbnail.chunks(4)) {
+            for (pixel, shade) in pixels.iter().enumerate() {
+                *byte |= (shade & 3) << (6 - pixel * 2);
+            }
+        }
+        writer.bytes.extend_from_slice(&thumbnail);
+        if self.version >= NAMED_VERSION {
+            writer.bytes(self.name.as_bytes());
+        }
+    }
+}
+
+/// Write a state: the header, then `sections` in order.
+pub fn write_state(header: &StateHeader, sections: &[(&[u8; 4], &dyn Snapshot)]) -> Vec<u8> {
+    let mut writer = StateWriter::default();
+    header.write(&mut writer);
+    write_sections(&mut writer, sections);
+    writer.bytes
+}
+/// Write `sections` without a header, for snapshots kept in memory by
+/// this version only.
+pub fn write_snapshot(sections: &[(&[u8; 4], &dyn Snapshot)]) -> Vec<u8> {
+    let mut writer = StateWriter::default();
+    write_sections(&mut writer, sections);
+    writer.bytes
+}
+fn write_sections(writer: &mut StateWriter, sections: &[(&[u8; 4], &dyn Snapshot)]) {
+    for (tag, component) in sections {
+        writer.bytes.extend_from_slice(*tag);
+        let start = writer.bytes.len();
+        writer.u32(0);
+        component.save(writer);
+        let length = (writer.bytes.len() - start - 4) as u32;
+        writer.bytes[start..start + 4].copy_from_slice(&length.to_le_bytes());
+    }
+}
+
+/// Restore the `sections` of `state`, which must have been made with the
+/// ROM whose CRC-32 is `rom_crc`. On error the sections are left as they
+/// were.
+pub fn read_state(
+    state: &[u8],
+    rom_crc: u32,
+    sections: &mut [(&[u8; 4], &mut dyn Snapshot)],
+) -> Result<StateHeader, StateError> {
+    let mut reader = StateReader::new(state);
+    let header = StateHeader::read(&mut reader)?;
+    if header.rom_crc != rom_crc {
+        return Err(StateError::WrongRom(header.rom_crc));
+    }
+    read_sections(&mut reader, header.version, sections)?;
+    Ok(header)
+}
+/// Restore the `sections` of a snapshot made by `write_snapshot`. On error
+/// the sections are left as they were.
+pub fn read_snapshot(
+    snapshot: &[u8],
+    sections: &mut [(&[u8; 4], &mut dyn Snapshot)],
+) -> Result<(), StateError> {
+    read_sections(&mut StateReader::new(snapshot), STATE_VERSION, sections)
+}
+fn read_sections(
+    reader: &mut StateReader,
+    version: u16,
+    sections: &mut [(&[u8; 4], &mut dyn Snapshot)],
+) -> Result<(), StateError> {
+    // The whole state is read before any section is applied.
+    let mut bodies = Vec::new();
+    while !reader.is_empty() {
+        let tag = reader.take(4)?;
+        let length = reader.u32()? as usize;
+        bodies.push((tag, migrate(version, tag, reader.take(length)?)));
+    }
+    // What each component held before, put back if a later one fails.
+    let mut previous: Vec<(usize, Vec<u8>)> = Vec::new();
+    for (tag, body) in bodies {
+        let Some(index) = sections.iter().position(|(known, _)| *known == tag) else {
+            continue;
+        };
+        let mut writer = StateWriter::default();
+        sections[index].1.save(&mut writer);
+        previous.push((index, writer.bytes));
+        if let Err(error) = sections[index].1.load(&mut StateReader::new(&body)) {
+            for (index, bytes) in previous.iter().rev() {
+                // Saved by this version, which always loads.
+                sections[*index].1.load(&mut StateReader::new(bytes)).ok();
+            }
+            return Err(error);
+        }
+    }
+    Ok(())
+}
+
+/// Give the name `name` to `state`, which is brought to the current
+/// version along the way.
+pub fn rename_state(state: &[u8], name: &str) -> Result<Vec<u8>, StateError> {
+    let mut reader = StateReader::new(state);
+    let mut header = StateHeader::read(&mut reader)?;
+    let version = header.version;
+    header.version = STATE_VERSION;
+    header.name = name.to_string();
+    let mut writer = StateWriter::default();
+    header.write(&mut writer);
+    while !reader.is_empty() {
+        let tag = reader.take(4)?;
+        let length = reader.u32()? as usize;
+        let body = migrate(version, tag, reader.take(length)?);
+        writer.bytes.extend_from_slice(tag);
+        writer.bytes(&body);
+    }
+    Ok(writer.bytes)
+}
+
+/// Bring the section `tag` of a state of `version` to the current format.
+fn migrate(_version: u16, _tag: &[u8], body: &[u8]) -> Vec<u8> {
+    // Version 2 only changed the header, the sections are the same.
+    body.to_vec()
+}
+
+#[derive(Debug, Default)]
+pub struct StateWriter {
+    bytes: Vec<u8>,
+}
+impl StateWriter {
+    pub fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+    pub fn u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    pub fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    pub fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    /// Bytes preceded by their count.
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.u32(bytes.len() as u32);
+        self.bytes.extend_from_slice(bytes);
+    }
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+/// Reads the fields of a section, those past its end read as 0.
+#[derive(Debug)]
+pub struct StateReader<'state> {
+    bytes: &'state [u8],
+}
+impl<'state> StateReader<'state> {
+    pub fn new(bytes: &'state [u8]) -> Self {
+        Self { bytes }
+    }
+    fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+    fn take(&mut self, count: usize) -> Result<&'state [u8], StateError> {
+        if count > self.bytes.len() {
+            return Err(StateError::Truncated);
+        }
+        let (taken, rest) = self.bytes.split_at(count);
+        self.bytes = rest;
+        Ok(taken)
+    }
+    /// The next `N` bytes, zeros past the end of the section.
+    fn field<const N: usize>(&mut self) -> Result<[u8; N], StateError> {
+        if self.bytes.is_empty() {
+            return Ok([0; N]);
+        }
+        Ok(self.take(N)?.try_into().unwrap_or([0; N]))
+    }
+    pub fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.field::<1>()?[0])
+    }
+    pub fn bool(&mut self) -> Result<bool, StateError> {
+        Ok(self.u8()? != 0)
+    }
+    pub fn u16(&mut self) -> Result<u16, StateError> {
+        Ok(u16::from_le_bytes(self.field()?))
+    }
+    pub fn u32(&mut self) -> Result<u32, StateError> {
+        Ok(u32::from_le_bytes(self.field()?))
+    }
+    pub fn u64(&mut self) -> Result<u64, StateError> {
+        Ok(u64::from_le_bytes(self.field()?))
+    }
+    pub fn bytes(&mut self) -> Result<&'state [u8], StateError> {
+        let count = self.u32()? as usize;
+        self.take(count)
+    }
+    /// Fill `buffer` from bytes saved by `StateWriter::bytes`, which must
+    /// have the same length.
+    pub fn bytes_into(&mut self, buffer: &mut [u8]) -> Result<(), StateError> {
+        let bytes = self.bytes()?;
+        if bytes.len() != buffer.len() {
+            return Err(StateError::Truncated);
+        }
+        buffer.copy_from_slice(bytes);
+        Ok(())
+    }
+}
+
+/// CRC-32 as used by zip and PNG.
+pub fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0u32, |crc, &byte| {
+        CRC_TABLE[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+/// CRC of each byte value, to process a byte at a time.
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut byte = 0;
+    while byte < 256 {
+        let mut crc = byte as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+            bit += 1;
+        }
+        table[byte] = crc;
+        byte += 1;
+    }
+    table
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Default, PartialEq)]
+    struct Counter {
+        value: u16,
+        extra: u8,
+    }
+    impl Snapshot for Counter {
+        fn save(&self, writer: &mut StateWriter) {
+            writer.u16(self.value);
+        }
+        fn load(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+            self.value = reader.u16()?;
+            // A field this version did not write.
+            self.extra = reader.u8()?;
+            Ok(())
+        }
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Blob(Vec<u8>);
+    impl Snapshot for Blob {
+        fn save(&self, writer: &mut StateWriter) {
+            writer.bytes(&self.0);
+        }
+        fn load(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+            reader.bytes_into(&mut self.0)
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        let thumbnail: Vec<u8> = (0..SCREEN_WIDTH * SCREEN_HEIGHT)
+            .map(|pixel| (pixel % 4) as u8)
+            .collect();
+        let header = StateHeader::new(crc32(b"rom"), 42, &thumbnail);
+        let counter = Counter {
+            value: 0x1234,
+            extra: 0,
+        };
+        let state = write_state(&header, &[(b"UNKN", &counter), (b"CNTR", &counter)]);
+        assert_eq!(StateHeader::parse(&state).unwrap(), header);
+
+        let mut loaded = Counter { value: 0, extra: 7 };
+        read_state(&state, crc32(b"rom"), &mut [(b"CNTR", &mut loaded)]).unwrap();
+        assert_eq!(loaded, counter);
+        assert_eq!(
+            read_state(&state, crc32(b"other"), &mut []).unwrap_err(),
+            StateError::WrongRom(crc32(b"rom"))
+        );
+        assert_eq!(
+            read_state(&state[..state.len() - 1], crc32(b"rom"), &mut []).unwrap_err(),
+            StateError::Truncated
+        );
+    }
+
+    #[test]
+    fn failed_load_changes_nothing() {
+        let header = StateHeader::new(0, 42, &[]);
+        let counter = Counter {
+            value: 0x1234,
+            extra: 0,
+        };
+        let state = write_state(
+            &header,
+            &[(b"CNTR", &counter), (b"BLOB", &Blob(vec![1; 3]))],
+        );
+        let mut loaded = Counter::default();
+        let mut blob = Blob(vec![0; 2]);
+        assert_eq!(
+            read_state(
+                &state,
+                0,
+                &mut [(b"CNTR", &mut loaded), (b"BLOB", &mut blob)]
+            )
+            .unwrap_err(),
+            StateError::Truncated
+        );
+        assert_eq!(loaded, Counter::default());
+        assert_eq!(blob, Blob(vec![0; 2]));
+    }
+
+    #[test]
+    fn rename_first_version() {
+        let mut header = StateHeader::new(0, 42, &[]);
+        header.version = 1;
+        let counter = Counter {
+            value: 0x1234,
+            extra: 0,
+        };
+        let state = write_state(&header, &[(b"CNTR", &counter)]);
+        assert_eq!(StateHeader::parse(&state).unwrap().name, "");
+
+        let state = rename_state(&state, "Before the boss").unwrap();
+        let header = StateHeader::parse(&state).unwrap();
+        assert_eq!(header.version, STATE_VERSION);
+        assert_eq!(header.name, "Before the boss");
+        let mut loaded = Counter::default();
+        read_state(&state, 0, &mut [(b"CNTR", &mut loaded)]).unwrap();
+        assert_eq!(loaded, counter);
+    }
+}
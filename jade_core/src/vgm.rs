@@ -0,0 +1,142 @@
+//! Recording of the sound register writes in the VGM format.
+//!
+//! Only the Game Boy DMG chip section of the header is filled in: every
+//! write to 0xFF10..=0xFF3F becomes a `0xB3 register value` command, and the
+//! time between writes is stored as waits in 44.1 kHz samples.
+
+use crate::apu::CPU_CLOCK;
+
+const VERSION: u32 = 0x161;
+const HEADER_SIZE: usize = 0x100;
+const SAMPLE_RATE: u64 = 44_100;
+
+const GB_DMG_WRITE: u8 = 0xB3;
+const WAIT: u8 = 0x61;
+const WAIT_NTSC_FRAME: u8 = 0x62;
+const WAIT_PAL_FRAME: u8 = 0x63;
+const WAIT_SHORT: u8 = 0x70;
+const END_OF_DATA: u8 = 0x66;
+
+#[derive(Debug, Clone, Default)]
+pub struct VgmRecorder {
+    /// Cycle at which the recording started, or was last rebased.
+    start: u64,
+    /// Samples recorded before `start`.
+    start_samples: u64,
+    data: Vec<u8>,
+    samples: u64,
+    /// Offset in `data` and sample count at the loop point.
+    loop_point: Option<(usize, u64)>,
+}
+impl VgmRecorder {
+    /// Start a recording at `cycle` T-cycles.
+    pub fn new(cycle: u64) -> Self {
+        Self {
+            start: cycle,
+            ..Default::default()
+        }
+    }
+    /// Record a write of `value` to the sound register at `address`.
+    pub fn write(&mut self, cycle: u64, address: u16, value: u8) {
+        self.wait_until(cycle);
+        self.data
+            .extend_from_slice(&[GB_DMG_WRITE, (address - 0xFF10) as u8, value]);
+    }
+    /// Playback restarts from `cycle` once the end of the recording is reached.
+    pub fn mark_loop(&mut self, cycle: u64) {
+        self.wait_until(cycle);
+        self.loop_point = Some((self.data.len(), self.samples));
+    }
+    pub fn has_loop(&self) -> bool {
+        self.loop_point.is_some()
+    }
+    /// Carry on from cycle `to` a recording made until cycle `from`, when
+    /// the clock of the console jumped, as on loading a state.
+    pub fn rebase(&mut self, from: u64, to: u64) {
+        self.wait_until(from);
+        self.start = to;
+        self.start_samples = self.samples;
+    }
+    fn wait_until(&mut self, cycle: u64) {
+        let elapsed = cycle.saturating_sub(self.start) * SAMPLE_RATE / CPU_CLOCK as u64;
+        let target = self.start_samples + elapsed;
+        let mut remaining = target.saturating_sub(self.samples);
+        self.samples += remaining;
+        while remaining > 0 {
+            match remaining {
+                735 => {
+                    self.data.push(WAIT_NTSC_FRAME);
+                    remaining = 0;
+                }
+                882 => {
+                    self.data.push(WAIT_PAL_FRAME);
+                    remaining = 0;
+                }
+                1..=16 => {
+                    self.data.push(WAIT_SHORT + remaining as u8 - 1);
+                    remaining = 0;
+                }
+                _ => {
+                    let wait = remaining.min(u16::MAX as u64);
+                    self.data.push(WAIT);
+                    self.data.extend_from_slice(&(wait as u16).to_le_bytes());
+                    remaining -= wait;
+                }
+            }
+        }
+    }
+    /// Stop the recording at `cycle` and return the VGM file.
+    pub fn finish(mut self, cycle: u64) -> Vec<u8> {
+        self.wait_until(cycle);
+        self.data.push(END_OF_DATA);
+
+        let mut file = vec![0; HEADER_SIZE];
+        let mut put = |offset: usize, value: u32| {
+            file[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        };
+        put(0x00, u32::from_le_bytes(*b"Vgm "));
+        put(0x04, (HEADER_SIZE + self.data.len() - 0x04) as u32);
+        put(0x08, VERSION);
+        put(0x18, self.samples as u32);
+        if let Some((offset, samples)) = self.loop_point {
+            // Offsets are relative to the field that stores them.
+            put(0x1C, (HEADER_SIZE + offset - 0x1C) as u32);
+            put(0x20, (self.samples - samples) as u32);
+        }
+        put(0x34, (HEADER_SIZE - 0x34) as u32);
+        put(0x80, CPU_CLOCK);
+        file.extend_from_slice(&self.data);
+        file
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_u32(file: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(file[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn record_writes_and_loop() {
+        // Just over 735 samples, a NTSC frame wait.
+        let frame = 69_906;
+        let mut recorder = VgmRecorder::new(1000);
+        recorder.write(1000, 0xFF26, 0x80);
+        recorder.mark_loop(1000 + frame);
+        recorder.write(1000 + frame, 0xFF30, 0x12);
+        let file = recorder.finish(1000 + 2 * frame);
+
+        assert_eq!(&file[0..4], b"Vgm ");
+        assert_eq!(read_u32(&file, 0x04) as usize, file.len() - 4);
+        assert_eq!(read_u32(&file, 0x18), 1470);
+        assert_eq!(read_u32(&file, 0x1C) as usize + 0x1C, HEADER_SIZE + 4);
+        assert_eq!(read_u32(&file, 0x20), 735);
+        assert_eq!(read_u32(&file, 0x80), CPU_CLOCK);
+        assert_eq!(
+            &file[HEADER_SIZE..],
+            &[0xB3, 0x16, 0x80, 0x62, 0xB3, 0x20, 0x12, 0x62, 0x66]
+        );
+    }
+}
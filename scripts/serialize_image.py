@@ -12,7 +12,7 @@ print(img.shape)
 img = img.reshape(img.shape[1], img.shape[0], 3)
 print(img.shape)
 with open("src/image.rs", "w") as f:
-    f.write("pub const IMAGE:[(u8,u8,u8);23040] = [\n")
+    f.write("pub static IMAGE:[(u8,u8,u8);23040] = [\n")
     for elem in img.reshape(img.shape[0] * img.shape[1], 3).tolist():
         elem = tuple(elem)
         f.write(f"{elem},\n")
@@ -23,15 +23,39 @@ impl<T> CircularBuffer<T> {
             current: 0,
         }
     }
-    pub fn append(&mut self, element: T) {
+    /// Append `element`, returning the oldest one it replaces once full.
+    pub fn append(&mut self, element: T) -> Option<T> {
         if self.inner.len() == self.inner.capacity() {
-            self.inner[self.current] = element;
+            let oldest = std::mem::replace(&mut self.inner[self.current], element);
             self.current = (self.current + 1) % self.inner.capacity();
+            Some(oldest)
         } else {
             self.inner.push(element);
+            None
+        }
+    }
+    /// Remove the last appended element.
+    pub fn pop(&mut self) -> Option<T> {
+        // Put the oldest element first, so that the next appends push again.
+        self.inner.rotate_left(self.current);
+        self.current = 0;
+        self.inner.pop()
+    }
+    /// Remove the oldest element.
+    pub fn pop_oldest(&mut self) -> Option<T> {
+        if self.inner.is_empty() {
+            return None;
+        }
+        let oldest = self.inner.remove(self.current);
+        if self.current == self.inner.len() {
+            self.current = 0;
         }
+        // The next appends push again, after the newest element.
+        self.inner.rotate_left(self.current);
+        self.current = 0;
+        Some(oldest)
     }
-    pub fn iter(&self) -> CircularBufferIterator<T> {
+    pub fn iter(&self) -> CircularBufferIterator<'_, T> {
         CircularBufferIterator {
             buffer: self,
             remaining: self.inner.len(),
@@ -40,6 +64,9 @@ impl<T> CircularBuffer<T> {
     pub fn len(&self) -> usize {
         self.inner.len()
     }
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
     pub fn capacity(&self) -> usize {
         self.inner.capacity()
     }
@@ -123,4 +150,27 @@ mod tests {
 
         println!("{}, {:?}", circular_buffer, circular_buffer);
     }
+    #[test]
+    fn pop_last_appended() {
+        let mut circular_buffer = CircularBuffer::with_capacity(3);
+        for element in 1..=4 {
+            circular_buffer.append(element);
+        }
+        assert_eq!(circular_buffer.pop(), Some(4));
+        circular_buffer.append(5);
+        circular_buffer.append(6);
+        assert_eq!(
+            circular_buffer.iter().copied().collect::<Vec<_>>(),
+            [3, 5, 6]
+        );
+        assert_eq!(circular_buffer.pop(), Some(6));
+        circular_buffer.append(7);
+        assert_eq!(circular_buffer.pop_oldest(), Some(3));
+        assert_eq!(circular_buffer.append(8), None);
+        assert_eq!(circular_buffer.append(9), Some(5));
+        assert_eq!(
+            circular_buffer.iter().copied().collect::<Vec<_>>(),
+            [7, 8, 9]
+        );
+    }
 }
@@ -0,0 +1,234 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use jade_core::{
+    boot::LogoAnimation,
+    console::{tick_lockstep, Console},
+    four_player::FourPlayerAdapter,
+    gbs::GbsPlayer,
+    model::Model,
+    sgb::{Rgb, SCREEN_WIDTH, SGB_WIDTH},
+};
+
+use crate::{
+    image::IMAGE,
+    logs::{LogLevel, LogMessage},
+    rewind::Rewind,
+    screen::{colors, shades},
+    speed::{FramePacer, FRAME_DURATION},
+    tracker::{Tracker, FRAME_RATE},
+};
+
+/// Shortest pause between two batches of frames, so that the UI thread
+/// gets the lock even when the speed is uncapped.
+const MIN_PAUSE: Duration = Duration::from_millis(1);
+
+/// The screens of the last emulated frame, published for the UI thread.
+#[derive(Debug, Clone, Default)]
+pub struct VideoFrame {
+    /// Frames emulated so far, to tell a new frame from the last one drawn.
+    pub number: u64,
+    /// Pixels of each console and their width, empty for the placeholder.
+    pub screens: Vec<(Vec<Rgb>, usize)>,
+    /// Measured speed of the emulation, 1 being the speed of the Game Boy.
+    pub speed: f32,
+}
+
+/// Everything the emulation thread advances, shared with the UI thread
+/// which sets the input and the speed.
+pub struct Emulation {
+    /// Consoles clocked in lockstep, the first one plays the sound.
+    pub consoles: Vec<Console>,
+    pub adapter: Option<FourPlayerAdapter>,
+    pub model: Model,
+    pub gbs: Option<GbsPlayer>,
+    pub tracker: Tracker,
+    /// Recent states of the first console, stepped back through while
+    /// `rewinding`.
+    pub rewind: Rewind,
+    pub rewinding: bool,
+    /// Logo scrolling down when a cartridge starts, in place of the boot ROM.
+    pub boot_animation: Option<LogoAnimation>,
+    pub pacer: FramePacer,
+    /// Multiplier of the Game Boy speed, `None` when uncapped.
+    pub speed: Option<f32>,
+    /// Messages for the logs, taken by the UI thread.
+    pub messages: Vec<LogMessage>,
+    frames: u64,
+}
+impl Default for Emulation {
+    fn default() -> Self {
+        Self {
+            consoles: vec![Console::new()],
+            adapter: None,
+            model: Model::default(),
+            gbs: None,
+            tracker: Tracker::default(),
+            rewind: Rewind::default(),
+            rewinding: false,
+            boot_animation: None,
+            pacer: FramePacer::new(Instant::now()),
+            speed: Some(1.),
+            messages: Vec::new(),
+            frames: 0,
+        }
+    }
+}
+impl Emulation {
+    /// Lock the emulation, even if the emulation thread panicked with it.
+    pub fn lock(emulation: &Mutex<Self>) -> MutexGuard<'_, Self> {
+        emulation.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+    /// Emulate the frames due at `now`, publishing the last one to `video`.
+    /// Returns the time until the next frame is due.
+    ///
+    /// The emulation is locked for one frame at a time, so that the UI
+    /// thread gets it in between.
+    fn run_due(emulation: &Mutex<Self>, now: Instant, video: &Mutex<VideoFrame>) -> Duration {
+        let due = {
+            let mut emulation = Self::lock(emulation);
+            let speed = emulation.speed;
+            emulation.pacer.frames_due(now, speed)
+        };
+        // Uncapped, emulate for a frame then publish.
+        let deadline = now + FRAME_DURATION;
+        let mut emulated = 0;
+        while emulated < due {
+            let mut emulation = Self::lock(emulation);
+            emulation.emulate_frame();
+            emulated += 1;
+            let now = Instant::now();
+            emulation.pacer.frame_done(now);
+            drop(emulation);
+            if now >= deadline {
+                break;
+            }
+            thread::yield_now();
+        }
+        let emulation = Self::lock(emulation);
+        if emulated > 0 {
+            *video.lock().unwrap_or_else(PoisonError::into_inner) = emulation.video_frame(now);
+        }
+        emulation.pacer.until_next(Instant::now(), emulation.speed)
+    }
+    /// Emulate a frame, or step back one while rewinding.
+    fn emulate_frame(&mut self) {
+        self.frames += 1;
+        let cycles = (self.model.clock() as f32 / FRAME_RATE) as u32;
+        if self.rewinding {
+            // One snapshot per frame, the emulation stays paused once
+            // the oldest one is reached.
+            if let Some(state) = self.rewind.step_back() {
+                if let Err(error) = self.consoles[0].load_snapshot(&state) {
+                    self.messages.push(LogMessage::new(
+                        LogLevel::Error,
+                        format!("Could not rewind: {error}"),
+                    ));
+                    self.rewind.clear();
+                }
+            }
+        } else {
+            tick_lockstep(&mut self.consoles, self.adapter.as_mut(), cycles);
+            let console = &self.consoles[0];
+            self.rewind.record(|| console.save_snapshot());
+        }
+        if let Some(gbs) = &mut self.gbs {
+            gbs.tick(cycles);
+        }
+        self.tracker.record(&self.consoles[0].apu);
+        let lcd = self.lcd();
+        for sgb in self
+            .consoles
+            .iter_mut()
+            .filter_map(|console| console.sgb.as_mut())
+        {
+            sgb.frame(&lcd);
+        }
+        if let Some(animation) = &mut self.boot_animation {
+            animation.tick();
+            if animation.is_done() {
+                self.boot_animation = None;
+            }
+        }
+    }
+    /// Color indices of the LCD. Until the PPU exists, the boot animation
+    /// then the placeholder image.
+    pub fn lcd(&self) -> Vec<u8> {
+        match &self.boot_animation {
+            Some(animation) => animation.frame(),
+            None => shades(&IMAGE),
+        }
+    }
+    fn video_frame(&self, now: Instant) -> VideoFrame {
+        let screens = self
+            .consoles
+            .iter()
+            .map(|console| match (&console.sgb, &self.boot_animation) {
+                (Some(sgb), _) => (sgb.output(), SGB_WIDTH),
+                (None, Some(animation)) => (colors(&animation.frame()), SCREEN_WIDTH),
+                (None, None) => (Vec::new(), SCREEN_WIDTH),
+            })
+            .collect();
+        VideoFrame {
+            number: self.frames,
+            screens,
+            speed: self.pacer.speed(now),
+        }
+    }
+}
+
+/// Runs the emulation at its own pace, apart from drawing and input.
+#[derive(Debug)]
+pub struct EmulationThread {
+    stop: Arc<AtomicBool>,
+    handle: JoinHandle<()>,
+}
+impl EmulationThread {
+    pub fn spawn(emulation: Arc<Mutex<Emulation>>, video: Arc<Mutex<VideoFrame>>) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        let handle = thread::spawn(move || {
+            Emulation::lock(&emulation).pacer = FramePacer::new(Instant::now());
+            while !stopped.load(Ordering::Relaxed) {
+                let wait = Emulation::run_due(&emulation, Instant::now(), &video);
+                thread::sleep(wait.clamp(MIN_PAUSE, FRAME_DURATION));
+            }
+        });
+        Self { stop, handle }
+    }
+    /// Whether the thread ended, which only happens on a panic until stopped.
+    pub fn is_finished(&self) -> bool {
+        self.handle.is_finished()
+    }
+    /// Stop the emulation, passing on the panic of the thread if any.
+    pub fn stop(self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Err(payload) = self.handle.join() {
+            std::panic::resume_unwind(payload);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn publish_frames() {
+        let emulation = Arc::new(Mutex::new(Emulation::default()));
+        let video = Arc::new(Mutex::new(VideoFrame::default()));
+        let thread = EmulationThread::spawn(emulation.clone(), video.clone());
+        thread::sleep(Duration::from_millis(100));
+        thread.stop();
+        let video = video.lock().unwrap();
+        assert!(video.number > 0);
+        assert_eq!(video.number, Emulation::lock(&emulation).frames);
+        assert_eq!(video.screens.len(), 1);
+    }
+}
@@ -0,0 +1,143 @@
+use std::{
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use color_eyre::{eyre::eyre, Result};
+
+use crate::speed::FRAME_DURATION;
+
+/// Most frames skipped between two drawn ones.
+pub const MAX_FRAMESKIP: usize = 9;
+/// Weight of the last draw in the average draw time.
+const DRAW_TIME_WEIGHT: f64 = 0.2;
+
+/// Frames left undrawn when the terminal is slower than the emulation.
+/// Only the drawing is skipped, the emulation thread runs every frame.
+///
+/// The number of skipped frames is read from the `[video]` table of the
+/// configuration file, automatic by default:
+///
+/// ```toml
+/// [video]
+/// frameskip = "auto"  # or 0 to 9
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct FrameSkip {
+    /// Frames skipped, `None` to follow the draw time.
+    manual: Option<usize>,
+    /// Average time taken to draw a frame.
+    draw_time: Duration,
+    /// Number of the last drawn frame.
+    last_drawn: Option<u64>,
+    /// When the last frame was drawn.
+    drawn_at: Option<Instant>,
+}
+impl FrameSkip {
+    pub fn new(manual: Option<usize>) -> Self {
+        Self {
+            manual: manual.map(|skip| skip.min(MAX_FRAMESKIP)),
+            ..Default::default()
+        }
+    }
+    /// Load the setting from `path`, automatic if it is not mentioned.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let config: toml::Table = std::fs::read_to_string(path)?.parse()?;
+        let Some(video) = config.get("video") else {
+            return Ok(Self::default());
+        };
+        let video = video
+            .as_table()
+            .ok_or_else(|| eyre!("`video` must be a table"))?;
+        if let Some(name) = video.keys().find(|name| *name != "frameskip") {
+            return Err(eyre!("unknown video setting `{name}`"));
+        }
+        match video.get("frameskip") {
+            None => Ok(Self::default()),
+            Some(value) if value.as_str() == Some("auto") => Ok(Self::default()),
+            Some(value) => value
+                .as_integer()
+                .and_then(|skip| usize::try_from(skip).ok())
+                .filter(|skip| *skip <= MAX_FRAMESKIP)
+                .map(|skip| Self::new(Some(skip)))
+                .ok_or_else(|| eyre!("invalid frameskip: {value}")),
+        }
+    }
+    pub fn is_automatic(&self) -> bool {
+        self.manual.is_none()
+    }
+    /// Go from automatic to 0, 1... up to `MAX_FRAMESKIP` then back.
+    pub fn cycle(&mut self) {
+        self.manual = match self.manual {
+            None => Some(0),
+            Some(MAX_FRAMESKIP) => None,
+            Some(skip) => Some(skip + 1),
+        };
+    }
+    /// Frames skipped between two drawn ones, while the emulation runs at
+    /// `speed` times the Game Boy.
+    pub fn skip(&self, speed: f32) -> usize {
+        if let Some(skip) = self.manual {
+            return skip;
+        }
+        if speed <= 0. {
+            return 0;
+        }
+        let frame_time = FRAME_DURATION.as_secs_f64() / speed as f64;
+        let frames = (self.draw_time.as_secs_f64() / frame_time).ceil() as usize;
+        frames.saturating_sub(1).min(MAX_FRAMESKIP)
+    }
+    /// Whether `frame` is to be drawn rather than skipped.
+    pub fn is_due(&self, frame: u64, speed: f32) -> bool {
+        self.last_drawn
+            .is_none_or(|last| frame > last + self.skip(speed) as u64)
+    }
+    /// Whether a redraw asked for by the input may happen at `now`, as
+    /// often as frames are drawn.
+    pub fn is_redraw_due(&self, now: Instant, speed: f32) -> bool {
+        let frame_time = FRAME_DURATION.div_f32(if speed > 0. { speed } else { 1. });
+        let interval = frame_time * (self.skip(speed) as u32 + 1);
+        self.drawn_at
+            .is_none_or(|drawn_at| now.saturating_duration_since(drawn_at) >= interval)
+    }
+    /// Count `frame` as drawn at `now` in `draw_time`.
+    pub fn drawn(&mut self, frame: u64, now: Instant, draw_time: Duration) {
+        self.last_drawn = Some(frame);
+        self.drawn_at = Some(now);
+        self.draw_time = if self.draw_time.is_zero() {
+            draw_time
+        } else {
+            self.draw_time.mul_f64(1. - DRAW_TIME_WEIGHT) + draw_time.mul_f64(DRAW_TIME_WEIGHT)
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skip_slow_draws() {
+        let now = Instant::now();
+        let mut frameskip = FrameSkip::default();
+        assert!(frameskip.is_due(1, 1.));
+        frameskip.drawn(1, now, FRAME_DURATION / 2);
+        assert_eq!(frameskip.skip(1.), 0);
+        assert!(frameskip.is_due(2, 1.));
+        // Drawing takes two and a half frames, ten at 4x.
+        frameskip = FrameSkip::default();
+        frameskip.drawn(1, now, FRAME_DURATION * 5 / 2);
+        assert_eq!(frameskip.skip(1.), 2);
+        assert!(!frameskip.is_due(3, 1.));
+        assert!(frameskip.is_due(4, 1.));
+        // Redraws for the input wait as long as three frames.
+        assert!(!frameskip.is_redraw_due(now + FRAME_DURATION * 2, 1.));
+        assert!(frameskip.is_redraw_due(now + FRAME_DURATION * 3, 1.));
+        assert_eq!(frameskip.skip(4.), MAX_FRAMESKIP);
+
+        frameskip.cycle();
+        assert_eq!(frameskip.skip(1.), 0);
+        frameskip.cycle();
+        assert_eq!(frameskip.skip(4.), 1);
+    }
+}
@@ -0,0 +1,129 @@
+use std::collections::HashSet;
+
+use jade_core::joypad::Button;
+use ratatui::{
+    layout::{Position, Rect},
+    style::{Color, Modifier, Style, Stylize},
+    text::{Line, Span},
+    widgets::{Block, BorderType, Paragraph, Widget},
+};
+
+/// Size of the gamepad, borders included.
+pub const GAMEPAD_WIDTH: u16 = 30;
+pub const GAMEPAD_HEIGHT: u16 = 15;
+
+/// Position and size of each button, relative to the inside of the border.
+const LAYOUT: [(Button, &str, u16, u16, u16); 8] = [
+    (Button::Up, "↑", 5, 0, 5),
+    (Button::Left, "←", 0, 3, 5),
+    (Button::Right, "→", 10, 3, 5),
+    (Button::Down, "↓", 5, 6, 5),
+    (Button::B, "B", 17, 3, 5),
+    (Button::A, "A", 23, 2, 5),
+    (Button::Select, "SELECT", 2, 10, 10),
+    (Button::Start, "START", 14, 10, 10),
+];
+const BUTTON_HEIGHT: u16 = 3;
+
+/// On-screen D-pad and buttons that can be clicked with the mouse.
+///
+/// A left click holds the button until the mouse is released, sliding
+/// over the D-pad follows the pointer. A right click latches the button
+/// down until it is right clicked again, so several can be held at once.
+#[derive(Debug, Default)]
+pub struct VirtualGamepad {
+    /// Screen area of each button, as of the last render.
+    areas: Vec<(Rect, Button)>,
+    clicked: Option<Button>,
+    latched: HashSet<Button>,
+    /// Buttons pressed on the joypad, from any source, to highlight.
+    highlighted: HashSet<Button>,
+}
+impl VirtualGamepad {
+    pub fn button_at(&self, column: u16, row: u16) -> Option<Button> {
+        self.areas
+            .iter()
+            .find(|(area, _)| area.contains(Position::new(column, row)))
+            .map(|(_, button)| *button)
+    }
+    /// Left button pressed or dragged at `column`, `row`.
+    pub fn click(&mut self, column: u16, row: u16) {
+        self.clicked = self.button_at(column, row);
+    }
+    pub fn release_click(&mut self) {
+        self.clicked = None;
+    }
+    /// Right button pressed at `column`, `row`.
+    pub fn toggle_latch(&mut self, column: u16, row: u16) {
+        if let Some(button) = self.button_at(column, row) {
+            if !self.latched.remove(&button) {
+                self.latched.insert(button);
+            }
+        }
+    }
+    /// Buttons held through the gamepad.
+    pub fn pressed(&self) -> impl Iterator<Item = Button> + '_ {
+        self.clicked.iter().chain(&self.latched).copied()
+    }
+    pub fn highlight(&mut self, pressed: HashSet<Button>) {
+        self.highlighted = pressed;
+    }
+}
+impl Widget for &mut VirtualGamepad {
+    fn render(self, area: Rect, buf: &mut ratatui::prelude::Buffer)
+    where
+        Self: Sized,
+    {
+        let block = Block::bordered()
+            .border_type(BorderType::Rounded)
+            .title(Span::styled(
+                " Gamepad ",
+                Style::default()
+                    .fg(Color::Magenta)
+                    .add_modifier(Modifier::BOLD),
+            ));
+        let inner = block.inner(area);
+        block.render(area, buf);
+
+        self.areas.clear();
+        for (button, label, x, y, width) in LAYOUT {
+            let button_area = Rect::new(inner.x + x, inner.y + y, width, BUTTON_HEIGHT)
+                .intersection(inner);
+            if button_area.is_empty() {
+                continue;
+            }
+            let mut style = Style::new();
+            if self.latched.contains(&button) {
+                style = style.yellow().bold();
+            } else if self.highlighted.contains(&button) {
+                style = style.green().bold();
+            }
+            Paragraph::new(Line::from(label).centered())
+                .block(Block::bordered().border_type(BorderType::Rounded))
+                .style(style)
+                .render(button_area, buf);
+            self.areas.push((button_area, button));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ratatui::buffer::Buffer;
+
+    use super::*;
+
+    #[test]
+    fn click_and_latch() {
+        let mut gamepad = VirtualGamepad::default();
+        let area = Rect::new(0, 0, GAMEPAD_WIDTH, GAMEPAD_HEIGHT);
+        gamepad.render(area, &mut Buffer::empty(area));
+
+        gamepad.click(26, 4);
+        assert_eq!(gamepad.pressed().collect::<Vec<_>>(), vec![Button::A]);
+        gamepad.toggle_latch(8, 2);
+        gamepad.release_click();
+        assert_eq!(gamepad.pressed().collect::<Vec<_>>(), vec![Button::Up]);
+        assert_eq!(gamepad.button_at(0, 0), None);
+    }
+}
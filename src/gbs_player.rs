@@ -0,0 +1,73 @@
+use jade_core::gbs::GbsPlayer;
+use ratatui::{
+    layout::{Constraint, Layout},
+    style::{Color, Modifier, Style, Stylize},
+    text::{Line, Span},
+    widgets::{Block, BorderType, List, ListState, StatefulWidget, Widget},
+};
+
+/// Track list shown in place of the screen while playing a GBS rip.
+pub struct GbsPlayerView<'player> {
+    player: &'player GbsPlayer,
+}
+impl<'player> GbsPlayerView<'player> {
+    pub fn new(player: &'player GbsPlayer) -> Self {
+        Self { player }
+    }
+}
+impl Widget for GbsPlayerView<'_> {
+    fn render(self, area: ratatui::prelude::Rect, buf: &mut ratatui::prelude::Buffer)
+    where
+        Self: Sized,
+    {
+        let header = self.player.header();
+        let elapsed = self.player.elapsed() as u64;
+        let block = Block::bordered()
+            .border_type(BorderType::Rounded)
+            .title(Span::styled(
+                format!(" {} ", header.title),
+                Style::default()
+                    .fg(Color::Magenta)
+                    .add_modifier(Modifier::BOLD),
+            ))
+            .title_bottom(
+                Line::from(format!(
+                    " Track {}/{}  {:02}:{:02} ",
+                    self.player.track() + 1,
+                    header.song_count,
+                    elapsed / 60,
+                    elapsed % 60
+                ))
+                .centered(),
+            );
+        let inner = block.inner(area);
+        block.render(area, buf);
+
+        let [author_space, copyright_space, note_space, list_space] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Length(1),
+            Constraint::Length(1),
+            Constraint::Min(1),
+        ])
+        .areas(inner);
+        Line::from(vec!["Author: ".bold(), header.author.as_str().into()])
+            .render(author_space, buf);
+        Line::from(vec!["Copyright: ".bold(), header.copyright.as_str().into()])
+            .render(copyright_space, buf);
+        // The play calls need the CPU, until then the APU and the tracker
+        // stay silent.
+        Line::from("No CPU yet: the driver does not run".dark_gray().italic())
+            .render(note_space, buf);
+
+        let tracks = (1..=header.song_count).map(|track| format!("Track {track:3}"));
+        let mut state = ListState::default().with_selected(Some(self.player.track() as usize));
+        StatefulWidget::render(
+            List::new(tracks)
+                .highlight_style(Style::new().green().bold())
+                .highlight_symbol("> "),
+            list_space,
+            buf,
+            &mut state,
+        );
+    }
+}
@@ -1,4 +1,4 @@
-pub const IMAGE:[(u8,u8,u8);23040] = [
+pub static IMAGE:[(u8,u8,u8);23040] = [
 (0, 0, 0),
 (0, 0, 0),
 (0, 0, 0),
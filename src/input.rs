@@ -0,0 +1,102 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    time::{Duration, Instant},
+};
+
+use jade_core::joypad::Button;
+
+/// How long a button stays pressed after a key press when the terminal
+/// does not report key releases. Key repeats extend it.
+pub const AUTO_RELEASE: Duration = Duration::from_millis(200);
+
+/// Buttons currently held on the keyboard, `B` is what the keys control.
+///
+/// When the terminal reports key releases buttons are held until released,
+/// otherwise each press holds the button for `AUTO_RELEASE`.
+#[derive(Debug)]
+pub struct HeldButtons<B = Button> {
+    reports_releases: bool,
+    /// Time at which each held button is released, if no release is reported.
+    held: HashMap<B, Option<Instant>>,
+}
+impl<B> Default for HeldButtons<B> {
+    fn default() -> Self {
+        Self {
+            reports_releases: false,
+            held: HashMap::new(),
+        }
+    }
+}
+impl<B: Copy + Eq + Hash> HeldButtons<B> {
+    pub fn new(reports_releases: bool) -> Self {
+        Self {
+            reports_releases,
+            held: HashMap::new(),
+        }
+    }
+    /// Returns true if `button` was not already held.
+    pub fn press(&mut self, button: B, now: Instant) -> bool {
+        let deadline = (!self.reports_releases).then_some(now + AUTO_RELEASE);
+        self.held.insert(button, deadline).is_none()
+    }
+    /// Returns true if `button` was held.
+    pub fn release(&mut self, button: B) -> bool {
+        self.held.remove(&button).is_some()
+    }
+    pub fn is_held(&self, button: B) -> bool {
+        self.held.contains_key(&button)
+    }
+    /// Release the buttons whose auto-release time has come.
+    pub fn expire(&mut self, now: Instant) -> Vec<B> {
+        let expired: Vec<B> = self
+            .held
+            .iter()
+            .filter(|(_, deadline)| deadline.is_some_and(|deadline| deadline <= now))
+            .map(|(button, _)| *button)
+            .collect();
+        for button in &expired {
+            self.held.remove(button);
+        }
+        expired
+    }
+    pub fn iter(&self) -> impl Iterator<Item = B> + '_ {
+        self.held.keys().copied()
+    }
+    /// Time left before the next auto-release, if any.
+    pub fn next_expiry(&self, now: Instant) -> Option<Duration> {
+        self.held
+            .values()
+            .flatten()
+            .min()
+            .map(|deadline| deadline.saturating_duration_since(now))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn auto_release_without_release_reports() {
+        let now = Instant::now();
+        let mut held = HeldButtons::new(false);
+        assert!(held.press(Button::A, now));
+        // A key repeat keeps the button held.
+        assert!(!held.press(Button::A, now + AUTO_RELEASE / 2));
+        assert!(held.expire(now + AUTO_RELEASE).is_empty());
+        assert_eq!(held.expire(now + AUTO_RELEASE * 2), vec![Button::A]);
+        assert!(!held.is_held(Button::A));
+    }
+
+    #[test]
+    fn hold_until_released() {
+        let now = Instant::now();
+        let mut held = HeldButtons::new(true);
+        held.press(Button::Up, now);
+        assert!(held.expire(now + AUTO_RELEASE * 10).is_empty());
+        assert_eq!(held.next_expiry(now), None);
+        assert!(held.release(Button::Up));
+        assert!(!held.is_held(Button::Up));
+    }
+}
@@ -0,0 +1,333 @@
+use std::{collections::HashMap, path::Path};
+
+use color_eyre::{eyre::eyre, Result};
+use crossterm::event::KeyCode;
+use jade_core::joypad::{Button, MAX_PLAYERS};
+
+use crate::{
+    macros::{MACRO_SLOTS, TURBO_RATE},
+    save_state::STATE_SLOTS,
+};
+
+/// Default location of the configuration file, relative to the working directory.
+pub const CONFIG_PATH: &str = "jade.toml";
+/// Keys of the interface itself, which cannot be bound.
+const RESERVED_KEYS: [KeyCode; 11] = [
+    KeyCode::Esc,
+    KeyCode::Char('q'),
+    KeyCode::Char('b'),
+    KeyCode::Char('t'),
+    KeyCode::Char('g'),
+    KeyCode::Char('v'),
+    KeyCode::Char('r'),
+    KeyCode::Char('l'),
+    KeyCode::Tab,
+    KeyCode::PageUp,
+    KeyCode::PageDown,
+];
+
+/// What a key does when pressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    Button(Button),
+    /// Press and release the button at the turbo rate while held.
+    Turbo(Button),
+    RecordMacro,
+    /// Record into or replay the macro slot.
+    Macro(usize),
+    /// Button of another joypad of a Super Game Boy, numbered from 1.
+    Player(usize, Button),
+    /// Step back through the last seconds while held.
+    Rewind,
+    /// Run at the fast-forward speed while held.
+    FastForward,
+    ToggleFastForward,
+    ToggleSlowMotion,
+}
+
+/// Maps the keyboard to the eight Game Boy buttons, turbo buttons, macros,
+/// the buttons of the other joypads of a Super Game Boy, rewinding and the
+/// emulation speed.
+///
+/// The mapping is read from the configuration file:
+///
+/// ```toml
+/// [keys]
+/// a = "x"
+/// up = "Up"
+///
+/// [turbo]
+/// rate = 15
+/// a = "s"
+///
+/// [macros]
+/// record = "m"
+/// slots = ["1", "2", "3", "4"]
+///
+/// [player2]
+/// a = "k"
+/// up = "i"
+///
+/// [rewind]
+/// key = "w"
+///
+/// [speed]
+/// hold = "Space"
+/// toggle = "f"
+/// slow = "o"
+/// ```
+///
+/// `[player2]` to `[player4]` have no keys by default. The keys of the
+/// interface and F1 to F10, which load and save states, cannot be bound.
+#[derive(Debug, Clone)]
+pub struct KeyMap {
+    bindings: HashMap<KeyCode, Action>,
+    turbo_rate: f32,
+}
+impl Default for KeyMap {
+    fn default() -> Self {
+        let mut bindings = HashMap::from([
+            (KeyCode::Right, Action::Button(Button::Right)),
+            (KeyCode::Left, Action::Button(Button::Left)),
+            (KeyCode::Up, Action::Button(Button::Up)),
+            (KeyCode::Down, Action::Button(Button::Down)),
+            (KeyCode::Char('x'), Action::Button(Button::A)),
+            (KeyCode::Char('z'), Action::Button(Button::B)),
+            (KeyCode::Backspace, Action::Button(Button::Select)),
+            (KeyCode::Enter, Action::Button(Button::Start)),
+            (KeyCode::Char('s'), Action::Turbo(Button::A)),
+            (KeyCode::Char('a'), Action::Turbo(Button::B)),
+            (KeyCode::Char('m'), Action::RecordMacro),
+            (KeyCode::Char('w'), Action::Rewind),
+            (KeyCode::Char(' '), Action::FastForward),
+            (KeyCode::Char('f'), Action::ToggleFastForward),
+            (KeyCode::Char('o'), Action::ToggleSlowMotion),
+        ]);
+        for slot in 0..MACRO_SLOTS {
+            let key = char::from_digit(slot as u32 + 1, 10).unwrap_or('0');
+            bindings.insert(KeyCode::Char(key), Action::Macro(slot));
+        }
+        Self {
+            bindings,
+            turbo_rate: TURBO_RATE,
+        }
+    }
+}
+impl KeyMap {
+    /// Load the mapping from `path`, actions it does not mention keep their default key.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let config: toml::Table = std::fs::read_to_string(path)?.parse()?;
+        let mut key_map = Self::default();
+        for (name, key) in table(&config, "keys")? {
+            let button = Button::from_name(name).ok_or_else(|| eyre!("unknown button `{name}`"))?;
+            key_map.bind_action(parse_value(name, key)?, Action::Button(button));
+        }
+        for (name, value) in table(&config, "turbo")? {
+            if name == "rate" {
+                key_map.turbo_rate = value
+                    .as_float()
+                    .or(value.as_integer().map(|rate| rate as f64))
+                    .filter(|rate| *rate > 0.)
+                    .ok_or_else(|| eyre!("invalid turbo rate: {value}"))?
+                    as f32;
+                continue;
+            }
+            let button = Button::from_name(name).ok_or_else(|| eyre!("unknown button `{name}`"))?;
+            key_map.bind_action(parse_value(name, value)?, Action::Turbo(button));
+        }
+        for (name, value) in table(&config, "macros")? {
+            match name.as_str() {
+                "record" => key_map.bind_action(parse_value(name, value)?, Action::RecordMacro),
+                "slots" => {
+                    let keys = value
+                        .as_array()
+                        .ok_or_else(|| eyre!("`slots` must be an array of keys"))?;
+                    for (slot, key) in keys.iter().take(MACRO_SLOTS).enumerate() {
+                        key_map.bind_action(parse_value(name, key)?, Action::Macro(slot));
+                    }
+                }
+                _ => return Err(eyre!("unknown macro setting `{name}`")),
+            }
+        }
+        for player in 1..MAX_PLAYERS {
+            for (name, key) in table(&config, &format!("player{}", player + 1))? {
+                let button =
+                    Button::from_name(name).ok_or_else(|| eyre!("unknown button `{name}`"))?;
+                key_map.bind_action(parse_value(name, key)?, Action::Player(player, button));
+            }
+        }
+        // Keys among the other settings of a feature.
+        for (table, name, action) in [
+            ("rewind", "key", Action::Rewind),
+            ("speed", "hold", Action::FastForward),
+            ("speed", "toggle", Action::ToggleFastForward),
+            ("speed", "slow", Action::ToggleSlowMotion),
+        ] {
+            if let Some(key) = config
+                .get(table)
+                .and_then(|table| table.as_table())
+                .and_then(|table| table.get(name))
+            {
+                key_map.bind_action(parse_value(name, key)?, action);
+            }
+        }
+        Ok(key_map)
+    }
+    /// Bind `key` to `button`, replacing the previous key of `button`.
+    pub fn bind(&mut self, key: KeyCode, button: Button) {
+        self.bind_action(key, Action::Button(button));
+    }
+    /// Bind `key` to `action`, replacing the previous key of `action`.
+    pub fn bind_action(&mut self, key: KeyCode, action: Action) {
+        self.bindings.retain(|_, bound| *bound != action);
+        self.bindings.insert(key, action);
+    }
+    pub fn action(&self, key: KeyCode) -> Option<Action> {
+        self.bindings.get(&key).copied()
+    }
+    /// The key bound to `action`, if any.
+    pub fn key(&self, action: Action) -> Option<KeyCode> {
+        self.bindings
+            .iter()
+            .find_map(|(key, bound)| (*bound == action).then_some(*key))
+    }
+    pub fn button(&self, key: KeyCode) -> Option<Button> {
+        match self.action(key) {
+            Some(Action::Button(button)) => Some(button),
+            _ => None,
+        }
+    }
+    /// Turbo presses per second.
+    pub fn turbo_rate(&self) -> f32 {
+        self.turbo_rate
+    }
+}
+
+/// The `name` table of `config`, empty if missing.
+fn table<'config>(
+    config: &'config toml::Table,
+    name: &str,
+) -> Result<impl Iterator<Item = (&'config String, &'config toml::Value)>> {
+    match config.get(name) {
+        None => Ok(None.into_iter().flatten()),
+        Some(value) => {
+            let table = value
+                .as_table()
+                .ok_or_else(|| eyre!("`{name}` must be a table"))?;
+            Ok(Some(table.iter()).into_iter().flatten())
+        }
+    }
+}
+fn parse_value(name: &str, key: &toml::Value) -> Result<KeyCode> {
+    let code = key
+        .as_str()
+        .and_then(parse_key)
+        .ok_or_else(|| eyre!("invalid key for `{name}`: {key}"))?;
+    let save_slot =
+        matches!(code, KeyCode::F(slot) if (1..=STATE_SLOTS).contains(&(slot as usize)));
+    if save_slot || RESERVED_KEYS.contains(&code) {
+        return Err(eyre!(
+            "{key} is used by the interface, it cannot be bound to `{name}`"
+        ));
+    }
+    Ok(code)
+}
+
+/// Parse a key name: a single character, a named key such as `Enter` or `F1`.
+pub fn parse_key(name: &str) -> Option<KeyCode> {
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Some(KeyCode::Char(c.to_ascii_lowercase()));
+    }
+    let key = match name.to_ascii_lowercase().as_str() {
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "enter" => KeyCode::Enter,
+        "space" => KeyCode::Char(' '),
+        "tab" => KeyCode::Tab,
+        "backspace" => KeyCode::Backspace,
+        name => {
+            let number = name.strip_prefix('f')?.parse().ok()?;
+            KeyCode::F(number)
+        }
+    };
+    Some(key)
+}
+/// Name of `key` as the instructions show it, which `parse_key` reads back.
+pub fn key_name(key: KeyCode) -> String {
+    match key {
+        KeyCode::Char(' ') => "Space".to_string(),
+        KeyCode::Char(c) => c.to_ascii_uppercase().to_string(),
+        KeyCode::F(number) => format!("F{number}"),
+        KeyCode::Up => "Up".to_string(),
+        KeyCode::Down => "Down".to_string(),
+        KeyCode::Left => "Left".to_string(),
+        KeyCode::Right => "Right".to_string(),
+        KeyCode::Enter => "Enter".to_string(),
+        KeyCode::Tab => "Tab".to_string(),
+        KeyCode::Backspace => "Backspace".to_string(),
+        key => key.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_parse_key() {
+        assert_eq!(parse_key("X"), Some(KeyCode::Char('x')));
+        assert_eq!(parse_key("Enter"), Some(KeyCode::Enter));
+        assert_eq!(parse_key("f5"), Some(KeyCode::F(5)));
+        for key in [KeyCode::Char(' '), KeyCode::Char('w'), KeyCode::F(12)] {
+            assert_eq!(parse_key(&key_name(key)), Some(key));
+        }
+        assert_eq!(parse_key("Hyper"), None);
+    }
+
+    #[test]
+    fn rebind_button() {
+        let mut key_map = KeyMap::default();
+        key_map.bind(KeyCode::Char('k'), Button::A);
+        assert_eq!(key_map.button(KeyCode::Char('k')), Some(Button::A));
+        assert_eq!(key_map.button(KeyCode::Char('x')), None);
+        assert_eq!(
+            key_map.action(KeyCode::Char('s')),
+            Some(Action::Turbo(Button::A))
+        );
+        assert_eq!(key_map.action(KeyCode::Char('2')), Some(Action::Macro(1)));
+        key_map.bind_action(KeyCode::Char('j'), Action::Rewind);
+        assert_eq!(key_map.key(Action::Rewind), Some(KeyCode::Char('j')));
+    }
+
+    #[test]
+    fn reject_interface_keys() {
+        let path = std::env::temp_dir().join(format!("jade-reserved-{}.toml", std::process::id()));
+        std::fs::write(&path, "[keys]\na = \"t\"\n").unwrap();
+        let error = KeyMap::load(&path).unwrap_err();
+        std::fs::write(&path, "[rewind]\nkey = \"F3\"\n").unwrap();
+        let slot_error = KeyMap::load(&path).unwrap_err();
+        std::fs::remove_file(path).unwrap();
+        assert!(error.to_string().contains("used by the interface"));
+        assert!(slot_error.to_string().contains("used by the interface"));
+    }
+
+    #[test]
+    fn load_other_players() {
+        let path = std::env::temp_dir().join(format!("jade-keys-{}.toml", std::process::id()));
+        std::fs::write(&path, "[player2]\na = \"k\"\n\n[player4]\nstart = \"F12\"\n").unwrap();
+        let key_map = KeyMap::load(&path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(
+            key_map.action(KeyCode::Char('k')),
+            Some(Action::Player(1, Button::A))
+        );
+        assert_eq!(
+            key_map.action(KeyCode::F(12)),
+            Some(Action::Player(3, Button::Start))
+        );
+        assert_eq!(key_map.button(KeyCode::Char('x')), Some(Button::A));
+    }
+}
@@ -1,5 +1,19 @@
 pub mod circular_buffer;
+pub mod emulation;
+pub mod frameskip;
+pub mod gamepad;
+pub mod gbs_player;
 pub mod image;
+pub mod input;
+pub mod keymap;
+pub mod link;
 pub mod logs;
+pub mod macros;
+pub mod printout;
+pub mod rewind;
+pub mod save_state;
 pub mod screen;
+pub mod speed;
+pub mod state_browser;
+pub mod tracker;
 pub mod user_interface;
@@ -0,0 +1,300 @@
+use std::{
+    collections::VecDeque,
+    io::{ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream},
+};
+
+#[cfg(unix)]
+use std::{
+    os::unix::{
+        fs::FileTypeExt,
+        net::{UnixListener, UnixStream},
+    },
+    path::PathBuf,
+};
+
+use color_eyre::{eyre::eyre, Result};
+use jade_core::{
+    infrared::{InfraredLink, Signal},
+    serial::{LinkCable, LinkMessage},
+};
+
+const TRANSFER: u8 = 0x01;
+const REPLY: u8 = 0x02;
+const LIGHT: u8 = 0x03;
+
+/// Bytes of a message of `kind`, the kind included.
+fn message_len(kind: u8) -> usize {
+    match kind {
+        // The light then the clock of the sender.
+        LIGHT => 10,
+        _ => 2,
+    }
+}
+
+/// Where two jade instances meet: `unix:/path/to/socket` or `host:port`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkAddress {
+    Tcp(String),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+impl LinkAddress {
+    pub fn parse(address: &str) -> Result<Self> {
+        if let Some(path) = address.strip_prefix("unix:") {
+            #[cfg(unix)]
+            return Ok(LinkAddress::Unix(PathBuf::from(path)));
+            #[cfg(not(unix))]
+            return Err(eyre!("unix sockets are not supported here: {path}"));
+        }
+        if !address.contains(':') {
+            return Err(eyre!(
+                "expected `host:port` or `unix:path`, got `{address}`"
+            ));
+        }
+        Ok(LinkAddress::Tcp(address.to_string()))
+    }
+}
+impl std::fmt::Display for LinkAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LinkAddress::Tcp(address) => write!(f, "{address}"),
+            #[cfg(unix)]
+            LinkAddress::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+impl Stream {
+    fn set_nonblocking(&self) -> std::io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => {
+                stream.set_nodelay(true)?;
+                stream.set_nonblocking(true)
+            }
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.set_nonblocking(true),
+        }
+    }
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.read(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.read(buf),
+        }
+    }
+    fn write_all(&mut self, buf: &[u8]) -> std::io::Result<()> {
+        let mut written = 0;
+        while written < buf.len() {
+            let result = match self {
+                Stream::Tcp(stream) => stream.write(&buf[written..]),
+                #[cfg(unix)]
+                Stream::Unix(stream) => stream.write(&buf[written..]),
+            };
+            match result {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(count) => written += count,
+                // The socket is non blocking, the other end is just slow.
+                Err(error) if error.kind() == ErrorKind::WouldBlock => std::thread::yield_now(),
+                Err(error) => return Err(error),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Link cable to another jade instance over a local socket.
+///
+/// Each message is its kind (transfer, reply or infrared light) then the
+/// byte sent. Infrared messages are followed by the little endian clock
+/// of the sender.
+///
+/// The same socket may carry both, each kind is queued apart until its
+/// port asks for it.
+pub struct SocketLink {
+    stream: Stream,
+    received: Vec<u8>,
+    serial: VecDeque<LinkMessage>,
+    light: VecDeque<Signal>,
+    closed: bool,
+}
+impl SocketLink {
+    fn new(stream: Stream) -> Result<Self> {
+        stream.set_nonblocking()?;
+        Ok(Self {
+            stream,
+            received: Vec::new(),
+            serial: VecDeque::new(),
+            light: VecDeque::new(),
+            closed: false,
+        })
+    }
+    /// Wait for the other instance to connect to `address`.
+    pub fn listen(address: &LinkAddress) -> Result<Self> {
+        let mut links = Self::listen_many(address, 1)?;
+        links.pop().ok_or_else(|| eyre!("no instance connected"))
+    }
+    /// Wait for `count` other instances to connect to `address`.
+    pub fn listen_many(address: &LinkAddress, count: usize) -> Result<Vec<Self>> {
+        let streams = match address {
+            LinkAddress::Tcp(address) => {
+                let listener = TcpListener::bind(address)?;
+                (0..count)
+                    .map(|_| Ok(Stream::Tcp(listener.accept()?.0)))
+                    .collect::<Result<Vec<_>>>()?
+            }
+            #[cfg(unix)]
+            LinkAddress::Unix(path) => {
+                // A socket left behind by a previous session.
+                if std::fs::metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+                    std::fs::remove_file(path)?;
+                }
+                let listener = UnixListener::bind(path)?;
+                (0..count)
+                    .map(|_| Ok(Stream::Unix(listener.accept()?.0)))
+                    .collect::<Result<Vec<_>>>()?
+            }
+        };
+        streams.into_iter().map(Self::new).collect()
+    }
+    /// Connect to the instance listening on `address`.
+    pub fn connect(address: &LinkAddress) -> Result<Self> {
+        let stream = match address {
+            LinkAddress::Tcp(address) => Stream::Tcp(TcpStream::connect(address)?),
+            #[cfg(unix)]
+            LinkAddress::Unix(path) => Stream::Unix(UnixStream::connect(path)?),
+        };
+        Self::new(stream)
+    }
+}
+impl SocketLink {
+    fn send_frame(&mut self, frame: &[u8]) {
+        if !self.closed && self.stream.write_all(frame).is_err() {
+            self.closed = true;
+        }
+    }
+    /// Read what arrived and queue the complete messages by kind.
+    fn receive_frames(&mut self) {
+        let mut buf = [0; 64];
+        while !self.closed {
+            match self.stream.read(&mut buf) {
+                Ok(0) => self.closed = true,
+                Ok(count) => self.received.extend_from_slice(&buf[..count]),
+                Err(error) if error.kind() == ErrorKind::WouldBlock => break,
+                Err(_) => self.closed = true,
+            }
+        }
+        while let Some(&kind) = self.received.first() {
+            let len = message_len(kind);
+            if self.received.len() < len {
+                break;
+            }
+            let frame: Vec<u8> = self.received.drain(..len).collect();
+            match frame.as_slice() {
+                [TRANSFER, byte] => self.serial.push_back(LinkMessage::Transfer(*byte)),
+                [REPLY, byte] => self.serial.push_back(LinkMessage::Reply(*byte)),
+                [LIGHT, on, at @ ..] => self.light.push_back(Signal {
+                    at: u64::from_le_bytes(at.try_into().unwrap_or_default()),
+                    on: *on != 0,
+                }),
+                _ => {}
+            }
+        }
+    }
+}
+impl LinkCable for SocketLink {
+    fn send(&mut self, message: LinkMessage) {
+        let frame = match message {
+            LinkMessage::Transfer(byte) => [TRANSFER, byte],
+            LinkMessage::Reply(byte) => [REPLY, byte],
+        };
+        self.send_frame(&frame);
+    }
+    fn receive(&mut self) -> Option<LinkMessage> {
+        self.receive_frames();
+        self.serial.pop_front()
+    }
+    fn is_closed(&self) -> bool {
+        self.closed && self.serial.is_empty()
+    }
+}
+impl InfraredLink for SocketLink {
+    fn send(&mut self, signal: Signal) {
+        let mut frame = vec![LIGHT, signal.on as u8];
+        frame.extend_from_slice(&signal.at.to_le_bytes());
+        self.send_frame(&frame);
+    }
+    fn receive(&mut self) -> Option<Signal> {
+        self.receive_frames();
+        self.light.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_addresses() {
+        assert_eq!(
+            LinkAddress::parse("127.0.0.1:7777").unwrap(),
+            LinkAddress::Tcp("127.0.0.1:7777".to_string())
+        );
+        #[cfg(unix)]
+        assert_eq!(
+            LinkAddress::parse("unix:/tmp/jade.sock").unwrap(),
+            LinkAddress::Unix(PathBuf::from("/tmp/jade.sock"))
+        );
+        assert!(LinkAddress::parse("jade").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn exchange_over_unix_socket() {
+        let path = std::env::temp_dir().join(format!("jade-link-{}.sock", std::process::id()));
+        let address = LinkAddress::Unix(path.clone());
+        let listener = {
+            let address = address.clone();
+            std::thread::spawn(move || SocketLink::listen(&address).unwrap())
+        };
+        let mut client = loop {
+            if let Ok(link) = SocketLink::connect(&address) {
+                break link;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        };
+        let mut server = listener.join().unwrap();
+        // Light sent first does not hide the transfer behind it.
+        InfraredLink::send(&mut client, Signal { at: 5, on: true });
+        LinkCable::send(&mut client, LinkMessage::Transfer(0x42));
+        let received = loop {
+            if let Some(message) = LinkCable::receive(&mut server) {
+                break message;
+            }
+        };
+        assert_eq!(received, LinkMessage::Transfer(0x42));
+        assert_eq!(
+            InfraredLink::receive(&mut server),
+            Some(Signal { at: 5, on: true })
+        );
+        InfraredLink::send(
+            &mut server,
+            Signal {
+                at: 70_224,
+                on: true,
+            },
+        );
+        let received = loop {
+            if let Some(signal) = InfraredLink::receive(&mut client) {
+                break signal;
+            }
+        };
+        assert_eq!(received.at, 70_224);
+        std::fs::remove_file(path).unwrap();
+    }
+}
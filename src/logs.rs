@@ -21,27 +21,27 @@ impl LogMessage {
         Self { log_level, message }
     }
 }
-impl<'line> Into<Line<'line>> for LogMessage {
-    fn into(self) -> Line<'line> {
+impl<'line> From<LogMessage> for Line<'line> {
+    fn from(log_message: LogMessage) -> Self {
         Line::from(vec![
-            match self.log_level {
+            match log_message.log_level {
                 LogLevel::Info => Span::styled("Info: ", Style::new().yellow().bold()),
                 LogLevel::Warning => Span::styled("Warning: ", Style::new().green().bold()),
                 LogLevel::Error => Span::styled("Error: ", Style::new().red().bold()),
             },
-            Span::styled(self.message, Style::new()),
+            Span::styled(log_message.message, Style::new()),
         ])
     }
 }
-impl<'line> Into<Line<'line>> for &'line LogMessage {
-    fn into(self) -> Line<'line> {
+impl<'line> From<&'line LogMessage> for Line<'line> {
+    fn from(log_message: &'line LogMessage) -> Self {
         Line::from(vec![
-            match self.log_level {
+            match log_message.log_level {
                 LogLevel::Info => Span::styled("[Info]: ", Style::new().green().bold()),
                 LogLevel::Warning => Span::styled("[Warning]: ", Style::new().yellow().bold()),
                 LogLevel::Error => Span::styled("[Error]: ", Style::new().red().bold()),
             },
-            Span::styled(self.message.as_str(), Style::new()),
+            Span::styled(log_message.message.as_str(), Style::new()),
         ])
     }
 }
@@ -60,17 +60,20 @@ impl Logs {
     pub fn len(&self) -> usize {
         self.circular_buffer.len()
     }
+    pub fn is_empty(&self) -> bool {
+        self.circular_buffer.is_empty()
+    }
 }
 impl From<CircularBuffer<LogMessage>> for Logs {
     fn from(circular_buffer: CircularBuffer<LogMessage>) -> Self {
         Self { circular_buffer }
     }
 }
-impl<'line> Into<Text<'line>> for &'line mut Logs {
-    fn into(self) -> Text<'line> {
-        let mut result = Vec::with_capacity(self.circular_buffer.len());
+impl<'line> From<&'line mut Logs> for Text<'line> {
+    fn from(logs: &'line mut Logs) -> Self {
+        let mut result = Vec::with_capacity(logs.circular_buffer.len());
         // println!("{}",)
-        for log_message in &self.circular_buffer {
+        for log_message in &logs.circular_buffer {
             result.push(log_message.into());
         }
         Text::from(result)
@@ -0,0 +1,242 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
+
+use jade_core::joypad::Button;
+
+/// Default number of turbo presses per second.
+pub const TURBO_RATE: f32 = 15.;
+/// Number of input macro slots.
+pub const MACRO_SLOTS: usize = 4;
+
+/// Buttons pressed and released repeatedly while their turbo key is held.
+#[derive(Debug, Clone)]
+pub struct Turbo {
+    rate: f32,
+    started: HashMap<Button, Instant>,
+}
+impl Default for Turbo {
+    fn default() -> Self {
+        Self::new(TURBO_RATE)
+    }
+}
+impl Turbo {
+    pub fn new(rate: f32) -> Self {
+        Self {
+            rate,
+            started: HashMap::new(),
+        }
+    }
+    pub fn rate(&self) -> f32 {
+        self.rate
+    }
+    pub fn start(&mut self, button: Button, now: Instant) {
+        self.started.entry(button).or_insert(now);
+    }
+    pub fn stop(&mut self, button: Button) {
+        self.started.remove(&button);
+    }
+    pub fn is_active(&self) -> bool {
+        !self.started.is_empty()
+    }
+    /// Turbo buttons in the pressed half of their cycle at `now`.
+    pub fn pressed(&self, now: Instant) -> impl Iterator<Item = Button> + '_ {
+        self.started.iter().filter_map(move |(button, started)| {
+            let half_periods = (now - *started).as_secs_f32() * self.rate * 2.;
+            (half_periods as u64).is_multiple_of(2).then_some(*button)
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct MacroEvent {
+    /// Time since the start of the macro.
+    at: Duration,
+    button: Button,
+    pressed: bool,
+}
+
+/// A recorded sequence of button presses and releases.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InputMacro {
+    events: Vec<MacroEvent>,
+    length: Duration,
+}
+impl InputMacro {
+    pub fn length(&self) -> Duration {
+        self.length
+    }
+    /// Buttons held `offset` after the start of the macro.
+    fn pressed(&self, offset: Duration) -> HashSet<Button> {
+        let mut pressed = HashSet::new();
+        for event in self.events.iter().take_while(|event| event.at <= offset) {
+            if event.pressed {
+                pressed.insert(event.button);
+            } else {
+                pressed.remove(&event.button);
+            }
+        }
+        pressed
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+enum MacroState {
+    #[default]
+    Idle,
+    /// Waiting for the slot to record into.
+    Armed,
+    Recording {
+        slot: usize,
+        start: Instant,
+        recorded: InputMacro,
+    },
+    Playing {
+        slot: usize,
+        start: Instant,
+    },
+}
+
+/// Input macro slots, recorded from and replayed to the joypad.
+///
+/// The record key arms the recorder, the next slot key starts recording
+/// into that slot and the record key stops it. A slot key otherwise
+/// replays its macro.
+#[derive(Debug, Clone)]
+pub struct Macros {
+    slots: Vec<Option<InputMacro>>,
+    state: MacroState,
+}
+impl Default for Macros {
+    fn default() -> Self {
+        Self::new(MACRO_SLOTS)
+    }
+}
+impl Macros {
+    pub fn new(slots: usize) -> Self {
+        Self {
+            slots: vec![None; slots],
+            state: MacroState::Idle,
+        }
+    }
+    pub fn toggle_recording(&mut self, now: Instant) {
+        self.state = match std::mem::take(&mut self.state) {
+            MacroState::Idle | MacroState::Playing { .. } => MacroState::Armed,
+            MacroState::Armed => MacroState::Idle,
+            MacroState::Recording {
+                slot,
+                start,
+                mut recorded,
+            } => {
+                recorded.length = now - start;
+                self.slots[slot] = Some(recorded);
+                MacroState::Idle
+            }
+        };
+    }
+    /// Handle the key of `slot`, either recording into it or replaying it.
+    pub fn select(&mut self, slot: usize, now: Instant) {
+        if slot >= self.slots.len() {
+            return;
+        }
+        match self.state {
+            MacroState::Armed => {
+                self.state = MacroState::Recording {
+                    slot,
+                    start: now,
+                    recorded: InputMacro::default(),
+                }
+            }
+            MacroState::Recording { .. } => {}
+            MacroState::Idle | MacroState::Playing { .. } => {
+                if self.slots[slot].is_some() {
+                    self.state = MacroState::Playing { slot, start: now };
+                }
+            }
+        }
+    }
+    /// Record a change of `button`, if recording.
+    pub fn record(&mut self, button: Button, pressed: bool, now: Instant) {
+        if let MacroState::Recording {
+            start, recorded, ..
+        } = &mut self.state
+        {
+            recorded.events.push(MacroEvent {
+                at: now - *start,
+                button,
+                pressed,
+            });
+        }
+    }
+    /// Buttons held by the macro being replayed at `now`.
+    pub fn pressed(&mut self, now: Instant) -> HashSet<Button> {
+        let MacroState::Playing { slot, start } = self.state else {
+            return HashSet::new();
+        };
+        let Some(input_macro) = &self.slots[slot] else {
+            return HashSet::new();
+        };
+        let offset = now - start;
+        if offset > input_macro.length {
+            self.state = MacroState::Idle;
+            return HashSet::new();
+        }
+        input_macro.pressed(offset)
+    }
+    /// Short description of what the macros are doing, for the status line.
+    pub fn status(&self, now: Instant) -> Option<String> {
+        match &self.state {
+            MacroState::Idle => None,
+            MacroState::Armed => Some("Macro: choose a slot to record".to_string()),
+            MacroState::Recording { slot, start, .. } => Some(format!(
+                "Recording macro {} {:.1}s",
+                slot + 1,
+                (now - *start).as_secs_f32()
+            )),
+            MacroState::Playing { slot, start } => Some(format!(
+                "Playing macro {} {:.1}s",
+                slot + 1,
+                (now - *start).as_secs_f32()
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn turbo_alternates() {
+        let now = Instant::now();
+        let mut turbo = Turbo::new(10.);
+        turbo.start(Button::A, now);
+        let pressed_at =
+            |ms| turbo.pressed(now + Duration::from_millis(ms)).collect::<Vec<_>>();
+        assert_eq!(pressed_at(0), vec![Button::A]);
+        assert_eq!(pressed_at(60), vec![]);
+        assert_eq!(pressed_at(110), vec![Button::A]);
+    }
+
+    #[test]
+    fn record_and_replay() {
+        let now = Instant::now();
+        let at = |ms| now + Duration::from_millis(ms);
+        let mut macros = Macros::new(4);
+        macros.toggle_recording(now);
+        macros.select(1, now);
+        macros.record(Button::Down, true, at(100));
+        macros.record(Button::Down, false, at(200));
+        macros.toggle_recording(at(300));
+        assert_eq!(macros.status(at(300)), None);
+
+        macros.select(1, at(1000));
+        assert!(macros.pressed(at(1050)).is_empty());
+        assert_eq!(macros.pressed(at(1150)), HashSet::from([Button::Down]));
+        assert!(macros.pressed(at(1250)).is_empty());
+        assert!(macros.status(at(1250)).is_some());
+        assert!(macros.pressed(at(1400)).is_empty());
+        assert_eq!(macros.status(at(1400)), None);
+    }
+}
@@ -1,14 +1,120 @@
-use color_eyre::Result;
-use jade_tui::user_interface::UserInterface;
+use std::panic::{self, AssertUnwindSafe};
+
+use color_eyre::{eyre::eyre, Result};
+use jade_core::{model::Model, serial::LinkCable};
+use jade_tui::{
+    frameskip::FrameSkip,
+    keymap::{KeyMap, CONFIG_PATH},
+    link::{LinkAddress, SocketLink},
+    rewind::RewindConfig,
+    speed::SpeedConfig,
+    user_interface::UserInterface,
+};
 // use ratatui::prelude::Backend;
 
 fn main() -> Result<()> {
     color_eyre::install()?;
+    let mut user_interface = UserInterface::default();
+    if std::path::Path::new(CONFIG_PATH).exists() {
+        user_interface.set_key_map(KeyMap::load(CONFIG_PATH)?);
+        user_interface.set_rewind_config(RewindConfig::load(CONFIG_PATH)?);
+        user_interface.set_speed_config(SpeedConfig::load(CONFIG_PATH)?);
+        user_interface.set_frameskip(FrameSkip::load(CONFIG_PATH)?);
+    }
+    // jade [--listen ADDRESS | --connect ADDRESS | --local-link | --four-player
+    //       | --four-player-listen ADDRESS | --printer] [--print-dir DIR]
+    //      [--ir-listen ADDRESS | --ir-connect ADDRESS] [--model MODEL]
+    //      [--boot-rom FILE] [FILE.gb | FILE.gbc | FILE.gbs]
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--listen" | "--connect" => {
+                let address = args
+                    .next()
+                    .ok_or_else(|| eyre!("{arg} expects `host:port` or `unix:path`"))?;
+                let address = LinkAddress::parse(&address)?;
+                let link = if arg == "--listen" {
+                    eprintln!("Waiting for the other jade instance on {address}...");
+                    SocketLink::listen(&address)?
+                } else {
+                    SocketLink::connect(&address)?
+                };
+                user_interface.connect_link(Box::new(link));
+            }
+            "--local-link" => user_interface.link_local_console(),
+            "--four-player" => user_interface.link_four_players(Vec::new()),
+            "--printer" => user_interface.attach_printer(),
+            "--print-dir" => {
+                let dir = args
+                    .next()
+                    .ok_or_else(|| eyre!("{arg} expects the directory of the printouts"))?;
+                user_interface.set_print_dir(dir);
+            }
+            "--model" => {
+                let name = args
+                    .next()
+                    .ok_or_else(|| eyre!("{arg} expects one of {}", model_names()))?;
+                let model = Model::from_name(&name).ok_or_else(|| {
+                    eyre!("unknown model `{name}`, expected one of {}", model_names())
+                })?;
+                user_interface.set_model(model);
+            }
+            "--boot-rom" => {
+                let path = args
+                    .next()
+                    .ok_or_else(|| eyre!("{arg} expects the path of a boot ROM"))?;
+                user_interface.load_boot_rom(path)?;
+            }
+            "--ir-listen" | "--ir-connect" => {
+                let address = args
+                    .next()
+                    .ok_or_else(|| eyre!("{arg} expects `host:port` or `unix:path`"))?;
+                let address = LinkAddress::parse(&address)?;
+                let link = if arg == "--ir-listen" {
+                    eprintln!(
+                        "Waiting for the infrared port of the other instance on {address}..."
+                    );
+                    SocketLink::listen(&address)?
+                } else {
+                    SocketLink::connect(&address)?
+                };
+                user_interface.connect_infrared(Box::new(link));
+            }
+            "--four-player-listen" => {
+                let address = args
+                    .next()
+                    .ok_or_else(|| eyre!("{arg} expects `host:port` or `unix:path`"))?;
+                let address = LinkAddress::parse(&address)?;
+                eprintln!("Waiting for players 2 to 4 on {address}...");
+                let links = SocketLink::listen_many(&address, 3)?
+                    .into_iter()
+                    .map(|link| Box::new(link) as Box<dyn LinkCable>)
+                    .collect();
+                user_interface.link_four_players(links);
+            }
+            path if path.to_ascii_lowercase().ends_with(".gbs") => user_interface.load_gbs(path)?,
+            path => user_interface.load_cartridge(path)?,
+        }
+    }
     let terminal = ratatui::init();
     // let be = terminal.backend_mut();
     // be.hide_cursor()?;
     // be.
-    let result = UserInterface::default().run(terminal);
+    // Keep the save even if the emulator panics.
+    let result = panic::catch_unwind(AssertUnwindSafe(|| user_interface.run(terminal)));
     ratatui::restore();
-    result
+    let saved = user_interface.flush_save();
+    match result {
+        Ok(result) => result.and(saved),
+        Err(payload) => {
+            if let Err(error) = saved {
+                eprintln!("Could not write the save: {error}");
+            }
+            panic::resume_unwind(payload)
+        }
+    }
+}
+
+fn model_names() -> String {
+    Model::ALL.map(Model::name).join(", ")
 }
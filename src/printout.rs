@@ -0,0 +1,104 @@
+use std::{fs::File, io::BufWriter, path::Path};
+
+use color_eyre::Result;
+use jade_core::printer::{Printout, PAPER_WIDTH};
+use ratatui::{
+    layout::Rect,
+    style::{Color, Modifier, Style},
+    text::Span,
+    widgets::{Block, BorderType, Widget},
+};
+
+/// Gray level of each printer shade, from white paper to black ink.
+const SHADES: [u8; 4] = [0xFF, 0xAA, 0x55, 0x00];
+
+/// Save `printout` as a grayscale PNG.
+pub fn write_png<P: AsRef<Path>>(printout: &Printout, path: P) -> Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, PAPER_WIDTH as u32, printout.height as u32);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    let pixels: Vec<u8> = printout
+        .pixels
+        .iter()
+        .map(|&shade| SHADES[shade as usize & 3])
+        .collect();
+    encoder.write_header()?.write_image_data(&pixels)?;
+    Ok(())
+}
+
+/// The paper coming out of the printer, scaled to the width of the pane.
+///
+/// Follows the last printed rows unless scrolled back.
+#[derive(Debug, Default)]
+pub struct PaperView {
+    /// First pixel row shown, `None` to follow the end of the paper.
+    scroll: Option<usize>,
+}
+impl PaperView {
+    /// Scroll by `rows` pixel rows of the paper, towards its end if positive.
+    pub fn scroll(&mut self, rows: isize, paper: &[u8]) {
+        let end = paper.len() / PAPER_WIDTH;
+        let top = self.scroll.unwrap_or(end).saturating_add_signed(rows);
+        self.scroll = (top < end).then_some(top);
+    }
+    pub fn render(&self, paper: &[u8], area: Rect, buf: &mut ratatui::prelude::Buffer) {
+        let block = Block::bordered()
+            .border_type(BorderType::Rounded)
+            .title(Span::styled(
+                " Printer ",
+                Style::default()
+                    .fg(Color::Magenta)
+                    .add_modifier(Modifier::BOLD),
+            ));
+        let inner = block.inner(area);
+        block.render(area, buf);
+        if inner.is_empty() {
+            return;
+        }
+        // Each cell shows two pixel rows with a half block.
+        let scale = PAPER_WIDTH as f32 / inner.width as f32;
+        let rows = paper.len() / PAPER_WIDTH;
+        let visible = (inner.height as f32 * 2. * scale) as usize;
+        let top = self.scroll.unwrap_or(rows.saturating_sub(visible));
+        let shade = |x: u16, y: usize| {
+            let x = ((x as f32 * scale) as usize).min(PAPER_WIDTH - 1);
+            let y = top + (y as f32 * scale) as usize;
+            paper.get(y * PAPER_WIDTH + x).map(|&shade| {
+                let gray = SHADES[shade as usize & 3];
+                Color::Rgb(gray, gray, gray)
+            })
+        };
+        for row in 0..inner.height {
+            for column in 0..inner.width {
+                let y = row as usize * 2;
+                let Some(upper) = shade(column, y) else {
+                    // Past the end of the paper.
+                    break;
+                };
+                buf[(inner.x + column, inner.y + row)]
+                    .set_char('▀')
+                    .set_fg(upper)
+                    .set_bg(shade(column, y + 1).unwrap_or(Color::Reset));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn save_printout() {
+        let path = std::env::temp_dir().join(format!("jade-print-{}.png", std::process::id()));
+        let printout = Printout {
+            height: 2,
+            pixels: vec![3; PAPER_WIDTH * 2],
+        };
+        write_png(&printout, &path).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        assert_eq!(&bytes[1..4], b"PNG");
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,301 @@
+use std::{path::Path, sync::Arc};
+
+use color_eyre::{eyre::eyre, Result};
+
+use crate::{circular_buffer::CircularBuffer, tracker::FRAME_RATE};
+
+/// Every so many snapshots one is kept whole, the others only keep what
+/// changed since it.
+const KEYFRAME_INTERVAL: usize = 16;
+
+/// How far back rewinding goes and how much memory it may take.
+///
+/// Read from the `[rewind]` table of the configuration file:
+///
+/// ```toml
+/// [rewind]
+/// key = "w"
+/// seconds = 30
+/// memory = 64     # MiB
+/// interval = 2    # frames between snapshots
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RewindConfig {
+    pub seconds: f32,
+    /// Memory budget of the snapshots, in bytes.
+    pub memory: usize,
+    /// Frames between two snapshots, rewinding plays one per frame.
+    pub interval: u32,
+}
+impl Default for RewindConfig {
+    fn default() -> Self {
+        Self {
+            seconds: 30.,
+            memory: 64 << 20,
+            interval: 2,
+        }
+    }
+}
+impl RewindConfig {
+    /// Snapshots taken over `seconds`.
+    fn depth(&self) -> usize {
+        ((self.seconds * FRAME_RATE / self.interval as f32) as usize).max(1)
+    }
+    /// Load the configuration from `path`, settings it does not mention
+    /// keep their default.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let config: toml::Table = std::fs::read_to_string(path)?.parse()?;
+        let mut rewind = Self::default();
+        let Some(table) = config.get("rewind") else {
+            return Ok(rewind);
+        };
+        let table = table
+            .as_table()
+            .ok_or_else(|| eyre!("`rewind` must be a table"))?;
+        for (name, value) in table {
+            // Bound by the key map.
+            if name == "key" {
+                continue;
+            }
+            let number = value
+                .as_float()
+                .or(value.as_integer().map(|number| number as f64))
+                .filter(|number| *number > 0.)
+                .ok_or_else(|| eyre!("invalid rewind {name}: {value}"))?;
+            match name.as_str() {
+                "seconds" => rewind.seconds = number as f32,
+                "memory" => rewind.memory = (number * (1 << 20) as f64) as usize,
+                "interval" => rewind.interval = number.max(1.) as u32,
+                _ => return Err(eyre!("unknown rewind setting `{name}`")),
+            }
+        }
+        Ok(rewind)
+    }
+}
+
+#[derive(Debug)]
+struct Snapshot {
+    keyframe: Arc<Vec<u8>>,
+    /// Changes from the keyframe, `None` for the keyframe itself.
+    delta: Option<Vec<u8>>,
+}
+impl Snapshot {
+    /// Bytes held by the snapshot alone, the keyframe is counted apart.
+    fn size(&self) -> usize {
+        self.delta.as_ref().map_or(0, Vec::len)
+    }
+    fn state(&self) -> Vec<u8> {
+        match &self.delta {
+            Some(delta) => apply_delta(&self.keyframe, delta),
+            None => self.keyframe.to_vec(),
+        }
+    }
+}
+
+/// The last seconds of the emulation, as snapshots to step back through.
+#[derive(Debug)]
+pub struct Rewind {
+    config: RewindConfig,
+    /// As many as the configured seconds hold.
+    snapshots: CircularBuffer<Snapshot>,
+    /// Bytes held by the snapshots and their keyframes, kept within the
+    /// memory budget by dropping the oldest snapshots.
+    bytes: usize,
+    frames: u32,
+    keyframe: Option<Arc<Vec<u8>>>,
+    since_keyframe: usize,
+}
+impl Default for Rewind {
+    fn default() -> Self {
+        Self::new(RewindConfig::default())
+    }
+}
+impl Rewind {
+    pub fn new(config: RewindConfig) -> Self {
+        Self {
+            config,
+            snapshots: CircularBuffer::with_capacity(config.depth()),
+            bytes: 0,
+            frames: 0,
+            keyframe: None,
+            since_keyframe: 0,
+        }
+    }
+    /// Call once per emulated frame, `state` is called when a snapshot is due.
+    pub fn record(&mut self, state: impl FnOnce() -> Vec<u8>) {
+        self.frames += 1;
+        if self.frames < self.config.interval {
+            return;
+        }
+        self.frames = 0;
+        let state = state();
+        let snapshot = match &self.keyframe {
+            Some(keyframe) if self.since_keyframe < KEYFRAME_INTERVAL => {
+                self.since_keyframe += 1;
+                Snapshot {
+                    keyframe: keyframe.clone(),
+                    delta: Some(delta(keyframe, &state)),
+                }
+            }
+            _ => {
+                let keyframe = Arc::new(state);
+                self.bytes += keyframe.len();
+                if let Some(previous) = self.keyframe.replace(keyframe.clone()) {
+                    self.release(previous);
+                }
+                self.since_keyframe = 1;
+                Snapshot {
+                    keyframe,
+                    delta: None,
+                }
+            }
+        };
+        self.bytes += snapshot.size();
+        if let Some(oldest) = self.snapshots.append(snapshot) {
+            self.drop_snapshot(oldest);
+        }
+        // The last snapshot is kept whatever its size.
+        while self.bytes > self.config.memory && self.snapshots.len() > 1 {
+            if let Some(oldest) = self.snapshots.pop_oldest() {
+                self.drop_snapshot(oldest);
+            }
+        }
+    }
+    /// The state of the last snapshot, which is dropped.
+    pub fn step_back(&mut self) -> Option<Vec<u8>> {
+        let snapshot = self.snapshots.pop()?;
+        let state = snapshot.state();
+        self.drop_snapshot(snapshot);
+        // The next snapshot starts a new keyframe, the last one may be gone.
+        if let Some(keyframe) = self.keyframe.take() {
+            self.release(keyframe);
+        }
+        self.frames = 0;
+        Some(state)
+    }
+    fn drop_snapshot(&mut self, snapshot: Snapshot) {
+        self.bytes -= snapshot.size();
+        self.release(snapshot.keyframe);
+    }
+    /// Stop counting `keyframe` once nothing else holds it.
+    fn release(&mut self, keyframe: Arc<Vec<u8>>) {
+        if Arc::strong_count(&keyframe) == 1 {
+            self.bytes -= keyframe.len();
+        }
+    }
+    /// Seconds that can be rewound.
+    pub fn seconds(&self) -> f32 {
+        self.snapshots.len() as f32 * self.config.interval as f32 / FRAME_RATE
+    }
+    pub fn clear(&mut self) {
+        *self = Self::new(self.config);
+    }
+}
+
+/// `state` as the runs of bytes that differ from `base`: the count of equal
+/// bytes to skip, then the count of bytes that follow and the bytes.
+fn delta(base: &[u8], state: &[u8]) -> Vec<u8> {
+    let mut delta = Vec::new();
+    write_count(&mut delta, state.len());
+    let differs = |index: usize| base.get(index) != state.get(index);
+    let mut index = 0;
+    while index < state.len() {
+        let start = index;
+        while index < state.len() && !differs(index) {
+            index += 1;
+        }
+        if index == state.len() {
+            break;
+        }
+        let changed = index;
+        while index < state.len() && differs(index) {
+            index += 1;
+        }
+        write_count(&mut delta, changed - start);
+        write_count(&mut delta, index - changed);
+        delta.extend_from_slice(&state[changed..index]);
+    }
+    delta
+}
+fn apply_delta(base: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut bytes = delta.iter().copied();
+    let len = read_count(&mut bytes);
+    let mut state = base.to_vec();
+    state.resize(len, 0);
+    let mut index = 0;
+    while let Some(skip) = (bytes.len() > 0).then(|| read_count(&mut bytes)) {
+        index += skip;
+        let count = read_count(&mut bytes);
+        for byte in &mut state[index..index + count] {
+            *byte = bytes.next().unwrap_or_default();
+        }
+        index += count;
+    }
+    state
+}
+/// Write `count` 7 bits at a time, the high bit set when more follow.
+fn write_count(bytes: &mut Vec<u8>, mut count: usize) {
+    while count >= 0x80 {
+        bytes.push(count as u8 | 0x80);
+        count >>= 7;
+    }
+    bytes.push(count as u8);
+}
+fn read_count(bytes: &mut impl Iterator<Item = u8>) -> usize {
+    let mut count = 0;
+    for (shift, byte) in bytes.enumerate() {
+        count |= (byte as usize & 0x7F) << (shift * 7);
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn step_back_through_deltas() {
+        let mut rewind = Rewind::new(RewindConfig {
+            seconds: 1.,
+            memory: 1 << 20,
+            interval: 1,
+        });
+        let mut state = vec![0u8; 1000];
+        for frame in 0..100u32 {
+            state[frame as usize * 7] = frame as u8 + 1;
+            state[999] = frame as u8;
+            rewind.record(|| state.clone());
+        }
+        // One second at one snapshot per frame, 59 whole frames.
+        assert_eq!(rewind.seconds(), 59. / FRAME_RATE);
+        let last = rewind.step_back().unwrap();
+        assert_eq!(last, state);
+        let before = rewind.step_back().unwrap();
+        assert_eq!(before[99 * 7], 0);
+        assert_eq!(before[98 * 7], 99);
+        assert_eq!(before[999], 98);
+    }
+
+    #[test]
+    fn keep_within_memory() {
+        let mut rewind = Rewind::new(RewindConfig {
+            seconds: 10.,
+            memory: 4000,
+            interval: 1,
+        });
+        let mut state = vec![0u8; 1000];
+        for frame in 0..100u32 {
+            state[frame as usize * 7] = frame as u8 + 1;
+            rewind.record(|| state.clone());
+            assert!(rewind.bytes <= 4000);
+        }
+        // Far fewer than ten seconds fit, the newest are kept.
+        assert!(rewind.snapshots.len() < 100);
+        assert_eq!(rewind.step_back().unwrap(), state);
+        while rewind.step_back().is_some() {}
+        assert_eq!(rewind.bytes, 0);
+    }
+}
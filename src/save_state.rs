@@ -0,0 +1,23 @@
+use std::path::{Path, PathBuf};
+
+/// Number of save state slots, reached with F1 to F10.
+pub const STATE_SLOTS: usize = 10;
+
+/// Where the state of `slot` is kept, next to the ROM: `game.ss1` to
+/// `game.ss10`.
+pub fn state_path(rom: &Path, slot: usize) -> PathBuf {
+    rom.with_extension(format!("ss{slot}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn path_next_to_rom() {
+        assert_eq!(
+            state_path(Path::new("roms/tetris.gb"), 3),
+            Path::new("roms/tetris.ss3")
+        );
+    }
+}
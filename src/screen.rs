@@ -1,4 +1,4 @@
-#![allow(unused, dead_code)]
+use jade_core::sgb::{Rgb, SCREEN_WIDTH};
 use ratatui::{
     layout::Rect,
     style::Color,
@@ -9,39 +9,135 @@ use ratatui::{
 };
 
 use crate::image::IMAGE;
-// 160 x 144
-const RATIO: f32 = 160. / 144.;
-pub struct Screen;
-impl Widget for &Screen {
+
+/// A frame of the emulator, scaled to fit the area while keeping its
+/// aspect ratio: 160 x 144 on the Game Boy, 256 x 224 with the SGB border.
+#[derive(Debug, Clone)]
+pub struct Screen<'frame> {
+    title: Option<String>,
+    pixels: &'frame [Rgb],
+    width: usize,
+}
+impl Default for Screen<'static> {
+    fn default() -> Self {
+        Self::new(&IMAGE, SCREEN_WIDTH)
+    }
+}
+impl<'frame> Screen<'frame> {
+    /// Show `pixels`, row by row, `width` per row.
+    pub fn new(pixels: &'frame [Rgb], width: usize) -> Self {
+        Self {
+            title: None,
+            pixels,
+            width: width.max(1),
+        }
+    }
+    pub fn title(mut self, title: impl Into<String>) -> Self {
+        self.title = Some(title.into());
+        self
+    }
+    fn height(&self) -> usize {
+        self.pixels.len() / self.width
+    }
+}
+/// Color index of each pixel, from 0 for the lightest to 3, for the
+/// parts of the emulator expecting the output of the LCD.
+pub fn shades(pixels: &[Rgb]) -> Vec<u8> {
+    pixels
+        .iter()
+        .map(|&(r, g, b)| {
+            let luma = (r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000;
+            3 - (luma * 4 / 256) as u8
+        })
+        .collect()
+}
+/// Colors of the color indices from `shades`, in shades of gray.
+pub fn colors(shades: &[u8]) -> Vec<Rgb> {
+    const GRAYS: [u8; 4] = [0xFF, 0xAA, 0x55, 0x00];
+    shades
+        .iter()
+        .map(|&shade| {
+            let gray = GRAYS[shade as usize & 3];
+            (gray, gray, gray)
+        })
+        .collect()
+}
+impl Widget for &Screen<'_> {
     fn render(self, area: ratatui::prelude::Rect, buf: &mut ratatui::prelude::Buffer)
     where
         Self: Sized,
     {
-        // println!("{}, {}", area.height, area.width);q
         let frame = Block::bordered()
             .border_type(ratatui::widgets::BorderType::Rounded)
-            .title("Asdrubalino");
+            .title(self.title.as_deref().unwrap_or("Asdrubalino"));
+        let inner = frame.inner(area);
+        frame.render(area, buf);
+        let height = self.height();
+        if inner.is_empty() || height == 0 {
+            return;
+        }
 
+        // A cell is about twice as tall as it is wide.
+        let ratio = self.width as f32 / height as f32;
+        let columns = (inner.width as f32).min(inner.height as f32 * 2. * ratio);
+        let columns = (columns as u16).clamp(1, inner.width);
+        let rows = ((columns as f32 / 2. / ratio).round() as u16).clamp(1, inner.height);
+        let screen = Rect::new(
+            inner.x + (inner.width - columns) / 2,
+            inner.y + (inner.height - rows) / 2,
+            columns,
+            rows,
+        );
         Canvas::default()
-            .block(frame)
             .marker(ratatui::symbols::Marker::Dot)
-            .paint(|ctx| ctx.draw(&ScreenFrame))
-            .render(area, buf);
+            .paint(|ctx| {
+                ctx.draw(&ScreenFrame {
+                    pixels: self.pixels,
+                    width: self.width,
+                    columns: columns as usize,
+                    rows: rows as usize,
+                })
+            })
+            .render(screen, buf);
     }
 }
-struct ScreenFrame;
-impl Shape for ScreenFrame {
+/// The pixels of a screen, one painted in each cell of the canvas.
+struct ScreenFrame<'frame> {
+    pixels: &'frame [Rgb],
+    width: usize,
+    columns: usize,
+    rows: usize,
+}
+impl Shape for ScreenFrame<'_> {
     fn draw(&self, painter: &mut ratatui::widgets::canvas::Painter) {
-        for y in 0..144 {
-            for x in 0..160 {
-                // let r = (x as f32 / (159.) * 255.999) as u8;
-                // let g = (y as f32 / (143.) * 255.999) as u8;
-                // let b = 0;
-                let (r, g, b) = IMAGE[y * 160 + x];
-
+        let height = self.pixels.len() / self.width;
+        for y in 0..self.rows {
+            for x in 0..self.columns {
+                let (r, g, b) = self.pixels
+                    [y * height / self.rows * self.width + x * self.width / self.columns];
                 painter.paint(x, y, Color::Rgb(r, g, b));
-                // painter.paint(x, y, Color::Rgb(0, 0, 0));
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use ratatui::buffer::Buffer;
+
+    use super::*;
+
+    #[test]
+    fn keep_aspect_ratio() {
+        let pixels = vec![(255, 255, 255); 256 * 224];
+        let area = Rect::new(0, 0, 130, 40);
+        let mut buf = Buffer::empty(area);
+        Screen::new(&pixels, 256).render(area, &mut buf);
+        // 38 rows fit, so the frame is 86 columns wide, centered.
+        let drawn = (0..area.width)
+            .filter(|&x| buf[(x, 20)].symbol() == "•")
+            .collect::<Vec<_>>();
+        assert_eq!(drawn.len(), 86);
+        assert_eq!(drawn[0], 22);
+    }
+}
@@ -0,0 +1,176 @@
+use std::{
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use color_eyre::{eyre::eyre, Result};
+
+use crate::{circular_buffer::CircularBuffer, tracker::FRAME_RATE};
+
+/// Duration of a Game Boy frame, 1 / 59.7275 Hz.
+pub const FRAME_DURATION: Duration = Duration::from_nanos(16_742_706);
+/// Frames emulated at most to catch up after a stall, the rest are dropped.
+const MAX_LATE_FRAMES: f64 = 4.;
+/// Frames the measured speed is averaged over.
+const SPEED_WINDOW: usize = 60;
+
+/// Speeds of fast-forward and slow motion.
+///
+/// Read from the `[speed]` table of the configuration file:
+///
+/// ```toml
+/// [speed]
+/// hold = "Space"
+/// toggle = "f"
+/// slow = "o"
+/// fast_forward = 4    # or "uncapped"
+/// slow_motion = 0.5
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpeedConfig {
+    /// Multiplier of fast-forward, `None` to emulate as fast as possible.
+    pub fast_forward: Option<f32>,
+    pub slow_motion: f32,
+}
+impl Default for SpeedConfig {
+    fn default() -> Self {
+        Self {
+            fast_forward: Some(4.),
+            slow_motion: 0.5,
+        }
+    }
+}
+impl SpeedConfig {
+    /// Load the configuration from `path`, settings it does not mention
+    /// keep their default.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let config: toml::Table = std::fs::read_to_string(path)?.parse()?;
+        let mut speed = Self::default();
+        let Some(table) = config.get("speed") else {
+            return Ok(speed);
+        };
+        let table = table
+            .as_table()
+            .ok_or_else(|| eyre!("`speed` must be a table"))?;
+        for (name, value) in table {
+            let multiplier = || {
+                value
+                    .as_float()
+                    .or(value.as_integer().map(|multiplier| multiplier as f64))
+                    .filter(|multiplier| *multiplier > 0.)
+                    .map(|multiplier| multiplier as f32)
+                    .ok_or_else(|| eyre!("invalid {name} speed: {value}"))
+            };
+            match name.as_str() {
+                // Bound by the key map.
+                "hold" | "toggle" | "slow" => {}
+                "fast_forward" if value.as_str() == Some("uncapped") => speed.fast_forward = None,
+                "fast_forward" => speed.fast_forward = Some(multiplier()?),
+                "slow_motion" => speed.slow_motion = multiplier()?,
+                _ => return Err(eyre!("unknown speed setting `{name}`")),
+            }
+        }
+        Ok(speed)
+    }
+}
+
+/// Paces the emulation to 59.7275 frames per second times a multiplier.
+///
+/// Frames are owed as time goes by and paid when the emulation catches
+/// up, so the rate holds however long each iteration of the loop takes.
+#[derive(Debug, Clone)]
+pub struct FramePacer {
+    last: Instant,
+    /// Frames owed, fractions included.
+    owed: f64,
+    /// When the last frames were emulated, to measure the speed.
+    frames: CircularBuffer<Instant>,
+}
+impl FramePacer {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            last: now,
+            owed: 0.,
+            frames: CircularBuffer::with_capacity(SPEED_WINDOW),
+        }
+    }
+    /// Frames to emulate at `now` at `speed` times the Game Boy rate,
+    /// `None` for as many as possible until the next frame is drawn.
+    pub fn frames_due(&mut self, now: Instant, speed: Option<f32>) -> usize {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.last = now;
+        let Some(speed) = speed else {
+            self.owed = 0.;
+            return usize::MAX;
+        };
+        self.owed += elapsed * FRAME_RATE as f64 * speed as f64;
+        // Rather run slow for a moment than all at once after a stall.
+        self.owed = self.owed.min(MAX_LATE_FRAMES * speed.max(1.) as f64);
+        let due = self.owed.floor();
+        self.owed -= due;
+        due as usize
+    }
+    /// Time until the next frame is due at `speed`.
+    pub fn until_next(&self, now: Instant, speed: Option<f32>) -> Duration {
+        let Some(speed) = speed else {
+            return Duration::ZERO;
+        };
+        let owed = self.owed
+            + now.saturating_duration_since(self.last).as_secs_f64()
+                * FRAME_RATE as f64
+                * speed as f64;
+        let left = (1. - owed).max(0.) / (FRAME_RATE as f64 * speed as f64);
+        Duration::from_secs_f64(left)
+    }
+    /// Count a frame emulated at `now`.
+    pub fn frame_done(&mut self, now: Instant) {
+        self.frames.append(now);
+    }
+    /// Measured speed, 1 being the speed of the Game Boy.
+    pub fn speed(&self, now: Instant) -> f32 {
+        let oldest = self.frames.iter().next();
+        let Some(oldest) = oldest else {
+            return 0.;
+        };
+        let elapsed = now.saturating_duration_since(*oldest).as_secs_f32();
+        if elapsed == 0. {
+            return 0.;
+        }
+        self.frames.len() as f32 / (elapsed * FRAME_RATE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pace_frames() {
+        let start = Instant::now();
+        let mut pacer = FramePacer::new(start);
+        // A second of late iterations still makes 59.7275 frames.
+        let mut frames = 0;
+        for step in 1..=40 {
+            frames += pacer.frames_due(start + Duration::from_millis(25 * step), Some(1.));
+        }
+        assert_eq!(frames, 59);
+        assert!(pacer.until_next(start + Duration::from_secs(1), Some(1.)) < FRAME_DURATION);
+        // A stall does not owe more than a few frames.
+        assert_eq!(
+            pacer.frames_due(start + Duration::from_secs(5), Some(1.)),
+            4
+        );
+        // Half speed for a second.
+        let frames: usize = (1..=10)
+            .map(|step| {
+                let now = start + Duration::from_secs(5) + Duration::from_millis(100 * step);
+                pacer.frames_due(now, Some(0.5))
+            })
+            .sum();
+        assert_eq!(frames, 29);
+        assert_eq!(
+            pacer.frames_due(start + Duration::from_secs(7), None),
+            usize::MAX
+        );
+    }
+}
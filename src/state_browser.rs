@@ -0,0 +1,230 @@
+use std::path::Path;
+
+use crossterm::event::KeyCode;
+use jade_core::{sgb::SCREEN_WIDTH, state::StateHeader};
+use ratatui::{
+    layout::{Constraint, Layout},
+    style::{Color, Modifier, Style, Stylize},
+    text::{Line, Span},
+    widgets::{Block, BorderType, List, ListState, StatefulWidget, Widget},
+};
+
+use crate::{
+    save_state::{state_path, STATE_SLOTS},
+    screen::{colors, Screen},
+};
+
+/// What the player asked for from the browser.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BrowserAction {
+    Load(usize),
+    Delete(usize),
+    Rename(usize, String),
+    Close,
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    slot: usize,
+    /// `None` when the file is not a state this version can read.
+    header: Option<StateHeader>,
+}
+
+/// The save states of the cartridge, shown in place of the screen with a
+/// preview of the selected one.
+#[derive(Debug, Clone, Default)]
+pub struct StateBrowser {
+    entries: Vec<Entry>,
+    selected: usize,
+    /// Name being typed for the selected state.
+    renaming: Option<String>,
+}
+impl StateBrowser {
+    /// List the states saved next to `rom`.
+    pub fn scan(rom: &Path) -> Self {
+        let entries = (1..=STATE_SLOTS)
+            .filter_map(|slot| {
+                let state = std::fs::read(state_path(rom, slot)).ok()?;
+                Some(Entry {
+                    slot,
+                    header: StateHeader::parse(&state).ok(),
+                })
+            })
+            .collect();
+        Self {
+            entries,
+            ..Default::default()
+        }
+    }
+    /// Scan again, keeping the selection where it was.
+    pub fn rescan(&mut self, rom: &Path) {
+        let selected = self.selected;
+        *self = Self::scan(rom);
+        self.selected = selected.min(self.entries.len().saturating_sub(1));
+    }
+    fn selected_slot(&self) -> Option<usize> {
+        self.entries.get(self.selected).map(|entry| entry.slot)
+    }
+    pub fn on_key(&mut self, code: KeyCode) -> Option<BrowserAction> {
+        if let Some(name) = &mut self.renaming {
+            match code {
+                KeyCode::Char(c) => name.push(c),
+                KeyCode::Backspace => {
+                    name.pop();
+                }
+                KeyCode::Enter => {
+                    let name = self.renaming.take().unwrap_or_default();
+                    return Some(BrowserAction::Rename(self.selected_slot()?, name));
+                }
+                KeyCode::Esc => self.renaming = None,
+                _ => {}
+            }
+            return None;
+        }
+        match code {
+            KeyCode::Up => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Down => {
+                self.selected = (self.selected + 1).min(self.entries.len().saturating_sub(1))
+            }
+            KeyCode::Enter => return Some(BrowserAction::Load(self.selected_slot()?)),
+            KeyCode::Delete | KeyCode::Char('d') => {
+                return Some(BrowserAction::Delete(self.selected_slot()?))
+            }
+            KeyCode::Char('n') if self.selected_slot().is_some() => {
+                let name = self.entries[self.selected]
+                    .header
+                    .as_ref()
+                    .map(|header| header.name.clone())
+                    .unwrap_or_default();
+                self.renaming = Some(name);
+            }
+            KeyCode::Esc | KeyCode::Char('b') => return Some(BrowserAction::Close),
+            _ => {}
+        }
+        None
+    }
+}
+impl Widget for &StateBrowser {
+    fn render(self, area: ratatui::prelude::Rect, buf: &mut ratatui::prelude::Buffer)
+    where
+        Self: Sized,
+    {
+        let help = if self.renaming.is_some() {
+            " Name <Enter> Cancel <Esc> "
+        } else {
+            " Load <Enter> Delete <D> Name <N> Close <B> "
+        };
+        let block = Block::bordered()
+            .border_type(BorderType::Rounded)
+            .title(Span::styled(
+                " Save states ",
+                Style::default()
+                    .fg(Color::Magenta)
+                    .add_modifier(Modifier::BOLD),
+            ))
+            .title_bottom(Line::from(help).centered());
+        let inner = block.inner(area);
+        block.render(area, buf);
+        if self.entries.is_empty() {
+            Line::from("No save states, Shift+F1 to F10 saves one")
+                .centered()
+                .render(inner, buf);
+            return;
+        }
+
+        let [list_space, preview_space] =
+            Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)])
+                .areas(inner);
+        let items = self.entries.iter().enumerate().map(|(index, entry)| {
+            let Some(header) = &entry.header else {
+                return format!("Slot {:2}  unreadable", entry.slot);
+            };
+            let name = match &self.renaming {
+                Some(name) if index == self.selected => format!("{name}_"),
+                _ => header.name.clone(),
+            };
+            format!(
+                "Slot {:2}  {}  {name}",
+                entry.slot,
+                format_time(header.saved_at)
+            )
+        });
+        let mut state = ListState::default().with_selected(Some(self.selected));
+        StatefulWidget::render(
+            List::new(items)
+                .highlight_style(Style::new().green().bold())
+                .highlight_symbol("> "),
+            list_space,
+            buf,
+            &mut state,
+        );
+        if let Some(header) = self
+            .entries
+            .get(self.selected)
+            .and_then(|entry| entry.header.as_ref())
+        {
+            let pixels = colors(&header.thumbnail);
+            Screen::new(&pixels, SCREEN_WIDTH)
+                .title(format!(" Slot {} ", self.entries[self.selected].slot))
+                .render(preview_space, buf);
+        }
+    }
+}
+
+/// `seconds` since the UNIX epoch as a UTC date and time.
+fn format_time(seconds: u64) -> String {
+    // Days to civil date, from Howard Hinnant's algorithm.
+    let days = (seconds / 86400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    let time = seconds % 86400;
+    format!(
+        "{year}-{month:02}-{day:02} {:02}:{:02}",
+        time / 3600,
+        time / 60 % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_format_time() {
+        assert_eq!(format_time(0), "1970-01-01 00:00");
+        assert_eq!(format_time(1_709_210_096), "2024-02-29 12:34");
+    }
+
+    #[test]
+    fn paint_thumbnail() {
+        let thumbnail = vec![3; SCREEN_WIDTH * jade_core::sgb::SCREEN_HEIGHT];
+        let browser = StateBrowser {
+            entries: vec![Entry {
+                slot: 1,
+                header: Some(StateHeader::new(0, 0, &thumbnail)),
+            }],
+            ..Default::default()
+        };
+        let area = ratatui::layout::Rect::new(0, 0, 120, 40);
+        let mut buf = ratatui::buffer::Buffer::empty(area);
+        browser.render(area, &mut buf);
+        // The preview is painted on a canvas like the screen.
+        let dots = buf
+            .content()
+            .iter()
+            .filter(|cell| cell.symbol() == "•" && cell.fg == Color::Rgb(0, 0, 0))
+            .count();
+        assert!(dots > 0);
+    }
+}
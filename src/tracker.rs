@@ -0,0 +1,145 @@
+use jade_core::apu::{Apu, Channel, ChannelState};
+use ratatui::{
+    layout::Constraint,
+    style::{Color, Modifier, Style, Stylize},
+    text::Span,
+    widgets::{Block, Row, Table, Widget},
+};
+
+use crate::circular_buffer::CircularBuffer;
+
+/// Refresh rate of the DMG LCD, the tracker records one row per frame at most.
+pub const FRAME_RATE: f32 = 59.7275;
+const NOTE_NAMES: [&str; 12] = [
+    "C-", "C#", "D-", "D#", "E-", "F-", "F#", "G-", "G#", "A-", "A#", "B-",
+];
+
+/// Convert a frequency in Hz to the closest tracker style note name, e.g. `A-4`.
+pub fn note_name(frequency: f32) -> String {
+    if !frequency.is_finite() || frequency <= 0. {
+        return "???".to_string();
+    }
+    let midi = (69. + 12. * (frequency / 440.).log2()).round() as i32;
+    if !(0..120).contains(&midi) {
+        return "???".to_string();
+    }
+    format!("{}{}", NOTE_NAMES[(midi % 12) as usize], midi / 12 - 1)
+}
+
+#[derive(Debug)]
+struct TrackerRow {
+    frame: u64,
+    channels: [Option<ChannelState>; 4],
+}
+
+/// Piano roll of the APU: a row is recorded every time a channel changes
+/// note, volume or duty, and the last `window` seconds are displayed.
+#[derive(Debug)]
+pub struct Tracker {
+    rows: CircularBuffer<TrackerRow>,
+    last: [Option<ChannelState>; 4],
+    frame: u64,
+    window: f32,
+}
+impl Default for Tracker {
+    fn default() -> Self {
+        Self::new(3.)
+    }
+}
+impl Tracker {
+    pub fn new(window: f32) -> Self {
+        Self {
+            rows: CircularBuffer::with_capacity((window * FRAME_RATE).ceil() as usize),
+            last: [None; 4],
+            frame: 0,
+            window,
+        }
+    }
+    /// Sample the channels of `apu`, must be called once per frame.
+    pub fn record(&mut self, apu: &Apu) {
+        let channels = Channel::ALL.map(|channel| {
+            let state = apu.channel(channel);
+            state.active.then_some(state)
+        });
+        if channels != self.last {
+            self.rows.append(TrackerRow {
+                frame: self.frame,
+                channels,
+            });
+            self.last = channels;
+        }
+        self.frame += 1;
+    }
+    fn cell(channel: Channel, state: &Option<ChannelState>) -> String {
+        let Some(state) = state else {
+            return "--- - -".to_string();
+        };
+        let note = match channel {
+            Channel::Noise => format!("N{:02X}", state.period),
+            _ => note_name(state.frequency),
+        };
+        let duty = state
+            .duty
+            .map(|duty| duty.to_string())
+            .unwrap_or("-".to_string());
+        format!("{} {:X} {}", note, state.volume, duty)
+    }
+}
+impl Widget for &Tracker {
+    fn render(self, area: ratatui::prelude::Rect, buf: &mut ratatui::prelude::Buffer)
+    where
+        Self: Sized,
+    {
+        let block = Block::bordered().title(Span::styled(
+            " Tracker ",
+            Style::default()
+                .fg(Color::Magenta)
+                .add_modifier(Modifier::BOLD),
+        ));
+        let oldest = self
+            .frame
+            .saturating_sub((self.window * FRAME_RATE) as u64);
+        // Two lines for the borders and one for the header.
+        let visible = area.height.saturating_sub(3) as usize;
+        let rows: Vec<Row> = self
+            .rows
+            .iter()
+            .filter(|row| row.frame >= oldest)
+            .map(|row| {
+                let mut cells = vec![format!("{:6.2}", row.frame as f32 / FRAME_RATE)];
+                for (channel, state) in Channel::ALL.iter().zip(&row.channels) {
+                    cells.push(Tracker::cell(*channel, state));
+                }
+                Row::new(cells)
+            })
+            .collect();
+        let skip = rows.len().saturating_sub(visible);
+        let header = Row::new(["Time", "Square 1", "Square 2", "Wave", "Noise"]).bold();
+        Table::new(
+            rows.into_iter().skip(skip),
+            [
+                Constraint::Length(6),
+                Constraint::Length(7),
+                Constraint::Length(7),
+                Constraint::Length(7),
+                Constraint::Length(7),
+            ],
+        )
+        .header(header)
+        .block(block)
+        .render(area, buf);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_note_names() {
+        assert_eq!(note_name(440.), "A-4");
+        assert_eq!(note_name(261.63), "C-4");
+        assert_eq!(note_name(466.16), "A#4");
+        assert_eq!(note_name(0.), "???");
+    }
+}
@@ -1,10 +1,12 @@
-#![allow(unused, dead_code)]
 use color_eyre::Result;
 use crossterm::{
-    cursor::Hide,
-    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    event::{
+        self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent, KeyEventKind,
+        KeyModifiers, KeyboardEnhancementFlags, MouseButton, MouseEvent, MouseEventKind,
+        PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+    },
     execute,
-    terminal::EnterAlternateScreen,
+    terminal::supports_keyboard_enhancement,
 };
 use ratatui::{
     layout::{Constraint, Direction, Layout},
@@ -14,67 +16,1042 @@ use ratatui::{
     DefaultTerminal, Frame,
 };
 
+use std::{
+    collections::HashSet,
+    io::stdout,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use jade_core::{
+    boot::{BootRom, LogoAnimation},
+    cartridge::Cartridge,
+    console::Console,
+    four_player::{FourPlayerAdapter, PLAYERS},
+    gbs::{Gbs, GbsPlayer},
+    infrared::{beam, InfraredLink},
+    joypad::{Button, MAX_PLAYERS},
+    model::Model,
+    printer::Printer,
+    serial::{cable, LinkCable},
+    state::rename_state,
+};
+
 use crate::{
+    emulation::{Emulation, EmulationThread, VideoFrame},
+    frameskip::FrameSkip,
+    gamepad::{VirtualGamepad, GAMEPAD_HEIGHT, GAMEPAD_WIDTH},
+    gbs_player::GbsPlayerView,
+    input::{HeldButtons, AUTO_RELEASE},
+    keymap::{key_name, Action, KeyMap},
     logs::{LogLevel, LogMessage, Logs},
+    macros::{Macros, Turbo},
+    printout::{write_png, PaperView},
+    rewind::{Rewind, RewindConfig},
+    save_state::{state_path, STATE_SLOTS},
     screen::Screen,
+    speed::SpeedConfig,
+    state_browser::{BrowserAction, StateBrowser},
 };
 
-#[derive(Default)]
+/// Pixel rows of paper scrolled by a page key.
+const PAPER_SCROLL: isize = 32;
+/// Time between two writes of a changed save RAM.
+const SAVE_INTERVAL: Duration = Duration::from_secs(10);
+/// Longest wait for input before looking for a new frame to draw.
+const POLL_INTERVAL: Duration = Duration::from_millis(4);
+
 pub struct UserInterface {
     running: bool,
     logs: Logs,
+    /// Advanced by the emulation thread while running.
+    emulation: Arc<Mutex<Emulation>>,
+    /// Last frame of the emulation thread.
+    video: Arc<Mutex<VideoFrame>>,
+    /// Whether something else than the frame changed since the last draw.
+    redraw: bool,
+    frameskip: FrameSkip,
+    /// Console receiving the input.
+    focus: usize,
+    /// Whether the model was chosen rather than read from the cartridge.
+    model_chosen: bool,
+    /// Boot ROM every console starts from.
+    boot_rom: Option<BootRom>,
+    /// ROM of the cartridge, which the save states are kept next to.
+    rom_path: Option<PathBuf>,
+    /// Shown in place of the screen while open.
+    state_browser: Option<StateBrowser>,
+    /// Where the battery-backed RAM of the first console is kept.
+    save_path: Option<PathBuf>,
+    last_save: Instant,
+    speed_config: SpeedConfig,
+    fast_forward: bool,
+    slow_motion: bool,
+    /// Game Boy Printer plugged in the first console.
+    printer: Option<Arc<Mutex<Printer>>>,
+    /// Where the printouts are saved.
+    print_dir: PathBuf,
+    /// Printouts saved since the start, numbering the files.
+    printouts: usize,
+    /// VGM recordings saved since the start, numbering the files.
+    recordings: usize,
+    paper_view: PaperView,
+    show_tracker: bool,
+    key_map: KeyMap,
+    held_buttons: HeldButtons<Action>,
+    turbo: Turbo,
+    macros: Macros,
+    /// Buttons pressed from the keyboard, including turbo, at the last update.
+    keyboard_buttons: HashSet<Button>,
+    /// Buttons to press on the joypad in focus, macros included.
+    pressed: HashSet<Button>,
+    gamepad: VirtualGamepad,
+    show_gamepad: bool,
+}
+impl Default for UserInterface {
+    fn default() -> Self {
+        Self {
+            running: false,
+            logs: Logs::default(),
+            emulation: Arc::default(),
+            video: Arc::default(),
+            redraw: true,
+            frameskip: FrameSkip::default(),
+            focus: 0,
+            model_chosen: false,
+            boot_rom: None,
+            rom_path: None,
+            state_browser: None,
+            save_path: None,
+            last_save: Instant::now(),
+            speed_config: SpeedConfig::default(),
+            fast_forward: false,
+            slow_motion: false,
+            printer: None,
+            print_dir: PathBuf::from("."),
+            printouts: 0,
+            recordings: 0,
+            paper_view: PaperView::default(),
+            show_tracker: false,
+            key_map: KeyMap::default(),
+            held_buttons: HeldButtons::default(),
+            turbo: Turbo::default(),
+            macros: Macros::default(),
+            keyboard_buttons: HashSet::new(),
+            pressed: HashSet::new(),
+            gamepad: VirtualGamepad::default(),
+            show_gamepad: false,
+        }
+    }
 }
 impl UserInterface {
+    pub fn set_key_map(&mut self, key_map: KeyMap) {
+        self.turbo = Turbo::new(key_map.turbo_rate());
+        self.key_map = key_map;
+    }
+    pub fn set_rewind_config(&mut self, config: RewindConfig) {
+        Emulation::lock(&self.emulation).rewind = Rewind::new(config);
+    }
+    pub fn set_speed_config(&mut self, config: SpeedConfig) {
+        self.speed_config = config;
+    }
+    pub fn set_frameskip(&mut self, frameskip: FrameSkip) {
+        self.frameskip = frameskip;
+    }
+    /// Load a GBS rip and show its track list in place of the screen.
+    pub fn load_gbs<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let gbs = Gbs::parse(&std::fs::read(path)?)?;
+        let header = &gbs.header;
+        self.logs.append(LogMessage::new(
+            LogLevel::Info,
+            format!(
+                "Loaded \"{}\" by {} ({}), {} tracks",
+                header.title, header.author, header.copyright, header.song_count
+            ),
+        ));
+        self.logs.append(LogMessage::new(
+            LogLevel::Info,
+            format!(
+                "Load {:#06X}, init {:#06X}, play {:#06X} at {:.2} Hz",
+                header.load_address,
+                header.init_address,
+                header.play_address,
+                header.play_rate()
+            ),
+        ));
+        Emulation::lock(&self.emulation).gbs = Some(GbsPlayer::new(gbs));
+        Ok(())
+    }
+    /// Start every console from the boot ROM in `path`.
+    pub fn load_boot_rom<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let boot_rom = BootRom::parse(&std::fs::read(path)?)?;
+        let family = if boot_rom.is_cgb() { "CGB" } else { "DMG" };
+        for console in &mut Emulation::lock(&self.emulation).consoles {
+            console.load_boot_rom(boot_rom.clone());
+        }
+        self.boot_rom = Some(boot_rom);
+        self.logs.append(LogMessage::new(
+            LogLevel::Info,
+            format!("Loaded a {family} boot ROM"),
+        ));
+        Ok(())
+    }
+    /// A console for another player, starting like the first one.
+    fn new_console(&self, emulation: &Emulation) -> Console {
+        let mut console = Console::with_model(emulation.model);
+        console.cartridge = emulation
+            .consoles
+            .first()
+            .and_then(|first| first.cartridge.clone());
+        if let Some(boot_rom) = &self.boot_rom {
+            console.load_boot_rom(boot_rom.clone());
+        }
+        console
+    }
+    /// Plug a link cable to another console into the serial port.
+    pub fn connect_link(&mut self, link: Box<dyn LinkCable>) {
+        Emulation::lock(&self.emulation).consoles[0]
+            .serial
+            .connect(link);
+        self.logs
+            .append(LogMessage::new(LogLevel::Info, "Link cable connected"));
+    }
+    /// Emulate `model` on every console, whatever the cartridge asks for.
+    /// A Super Game Boy is shown with its border.
+    pub fn set_model(&mut self, model: Model) {
+        self.model_chosen = true;
+        self.switch_model(model);
+    }
+    /// Replace the consoles by consoles of `model`, keeping their cartridge
+    /// and what is plugged in their ports.
+    fn switch_model(&mut self, model: Model) {
+        let mut emulation = Emulation::lock(&self.emulation);
+        emulation.model = model;
+        for index in 0..emulation.consoles.len() {
+            let mut console = self.new_console(&emulation);
+            let old = &mut emulation.consoles[index];
+            console.cartridge = old.cartridge.take();
+            if let Some(link) = old.serial.disconnect() {
+                console.serial.connect(link);
+            }
+            if let Some(link) = old.infrared.disconnect() {
+                console.infrared.connect(link);
+            }
+            emulation.consoles[index] = console;
+        }
+        emulation.rewind.clear();
+        drop(emulation);
+        self.logs.append(LogMessage::new(
+            LogLevel::Info,
+            format!("Emulating the {}", model.name().to_uppercase()),
+        ));
+    }
+    /// Insert the cartridge in `path` in every console, switching to the
+    /// model it asks for unless one was chosen.
+    ///
+    /// The RAM of a cartridge with a battery is read from the `.sav` file
+    /// next to it, and written back by `flush_save`.
+    pub fn load_cartridge<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let mut cartridge = Cartridge::parse(&std::fs::read(&path)?)?;
+        self.logs.append(LogMessage::new(
+            LogLevel::Info,
+            format!("Loaded \"{}\"", cartridge.title()),
+        ));
+        self.rom_path = Some(path.as_ref().to_path_buf());
+        self.save_path = None;
+        if cartridge.has_battery() {
+            let save_path = path.as_ref().with_extension("sav");
+            if save_path.exists() {
+                cartridge.load_battery_save(&std::fs::read(&save_path)?, unix_time())?;
+                self.logs.append(LogMessage::new(
+                    LogLevel::Info,
+                    format!("Save loaded from {}", save_path.display()),
+                ));
+            }
+            self.save_path = Some(save_path);
+        }
+        if !cartridge.has_valid_logo() {
+            self.logs.append(LogMessage::new(
+                LogLevel::Warning,
+                "The cartridge logo is not the expected one, real hardware would lock up",
+            ));
+        }
+        let model = cartridge.model();
+        let mut emulation = Emulation::lock(&self.emulation);
+        // Nothing runs a boot ROM until the CPU exists, the animation
+        // shows its logo meanwhile.
+        emulation.boot_animation = Some(LogoAnimation::new(cartridge.logo()));
+        emulation.rewind.clear();
+        for console in &mut emulation.consoles {
+            console.cartridge = Some(cartridge.clone());
+        }
+        let switch = !self.model_chosen && model != emulation.model;
+        drop(emulation);
+        if switch {
+            self.switch_model(model);
+        }
+        Ok(())
+    }
+    /// Write the battery-backed RAM of the first console to its `.sav` file.
+    ///
+    /// Called on quit, also after a panic, and every `SAVE_INTERVAL` while
+    /// the RAM changes. Clean RAM is left alone, a clock is always saved
+    /// since it keeps running.
+    pub fn flush_save(&mut self) -> Result<()> {
+        let mut emulation = Emulation::lock(&self.emulation);
+        let (Some(path), Some(cartridge)) = (&self.save_path, &mut emulation.consoles[0].cartridge)
+        else {
+            return Ok(());
+        };
+        if !cartridge.is_dirty() && cartridge.rtc().is_none() {
+            return Ok(());
+        }
+        std::fs::write(path, cartridge.battery_save(unix_time()))?;
+        self.last_save = Instant::now();
+        Ok(())
+    }
+    /// Write the save when the RAM is `dirty` and the last write is old
+    /// enough.
+    fn save_periodically(&mut self, dirty: bool) {
+        if !dirty || self.last_save.elapsed() < SAVE_INTERVAL {
+            return;
+        }
+        if let Err(error) = self.flush_save() {
+            // Wait for the next interval rather than retrying every frame.
+            self.last_save = Instant::now();
+            self.logs.append(LogMessage::new(
+                LogLevel::Error,
+                format!("Could not write the save: {error}"),
+            ));
+        }
+    }
+    /// Point the infrared port of the first console at another one.
+    pub fn connect_infrared(&mut self, link: Box<dyn InfraredLink>) {
+        Emulation::lock(&self.emulation).consoles[0]
+            .infrared
+            .connect(link);
+        self.logs
+            .append(LogMessage::new(LogLevel::Info, "Infrared port connected"));
+    }
+    /// Run a second console in this process, linked to the first one by
+    /// cable and infrared, and shown beside it.
+    pub fn link_local_console(&mut self) {
+        let (first, second) = cable();
+        let (first_light, second_light) = beam();
+        let mut emulation = Emulation::lock(&self.emulation);
+        let mut console = self.new_console(&emulation);
+        console.serial.connect(Box::new(second));
+        console.infrared.connect(Box::new(second_light));
+        emulation.consoles.truncate(1);
+        emulation.adapter = None;
+        emulation.consoles[0].serial.connect(Box::new(first));
+        emulation.consoles[0]
+            .infrared
+            .connect(Box::new(first_light));
+        emulation.consoles.push(console);
+        drop(emulation);
+        self.logs.append(LogMessage::new(
+            LogLevel::Info,
+            "Second console linked, <Tab> switches the controlled one",
+        ));
+    }
+    /// Link four players through a Four Player Adapter: the `remote`
+    /// instances take the last ports, consoles of this process the others.
+    pub fn link_four_players(&mut self, remote: Vec<Box<dyn LinkCable>>) {
+        let mut adapter = FourPlayerAdapter::new();
+        let local = PLAYERS.saturating_sub(remote.len()).max(1);
+        let mut emulation = Emulation::lock(&self.emulation);
+        while emulation.consoles.len() < local {
+            let console = self.new_console(&emulation);
+            emulation.consoles.push(console);
+        }
+        emulation.consoles.truncate(local);
+        self.focus = 0;
+        for (player, console) in emulation.consoles.iter_mut().enumerate() {
+            let (port, end) = cable();
+            adapter.connect(player, Box::new(port));
+            console.serial.connect(Box::new(end));
+        }
+        for (player, link) in (local..PLAYERS).zip(remote) {
+            adapter.connect(player, link);
+        }
+        emulation.adapter = Some(adapter);
+        drop(emulation);
+        self.logs.append(LogMessage::new(
+            LogLevel::Info,
+            format!("Four Player Adapter plugged, {local} players in this window"),
+        ));
+    }
+    /// Plug a Game Boy Printer in the first console, each printout is
+    /// saved as a PNG file.
+    pub fn attach_printer(&mut self) {
+        let printer = Arc::new(Mutex::new(Printer::new()));
+        Emulation::lock(&self.emulation).consoles[0]
+            .serial
+            .connect(Box::new(printer.clone()));
+        self.printer = Some(printer);
+        self.logs
+            .append(LogMessage::new(LogLevel::Info, "Game Boy Printer plugged"));
+    }
+    /// Save the printouts in `dir` rather than the working directory.
+    pub fn set_print_dir<P: AsRef<Path>>(&mut self, dir: P) {
+        self.print_dir = dir.as_ref().to_path_buf();
+    }
     /// Application main loop.
     pub fn run(&mut self, mut terminal: DefaultTerminal) -> Result<()> {
         terminal.hide_cursor()?;
+        // Key releases are only reported by terminals supporting the
+        // keyboard enhancement protocol.
+        let enhanced = matches!(supports_keyboard_enhancement(), Ok(true));
+        if enhanced {
+            execute!(
+                stdout(),
+                PushKeyboardEnhancementFlags(
+                    KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES
+                        | KeyboardEnhancementFlags::REPORT_EVENT_TYPES
+                )
+            )?;
+            self.logs.append(LogMessage::new(
+                LogLevel::Info,
+                "Keyboard enhancement enabled, key releases are tracked",
+            ));
+        } else {
+            self.logs.append(LogMessage::new(
+                LogLevel::Warning,
+                format!(
+                    "Key releases not supported, buttons are released after {} ms",
+                    AUTO_RELEASE.as_millis()
+                ),
+            ));
+        }
+        self.held_buttons = HeldButtons::new(enhanced);
+        let result = self.main_loop(&mut terminal);
+        if enhanced {
+            execute!(stdout(), PopKeyboardEnhancementFlags)?;
+        }
+        if self.show_gamepad {
+            execute!(stdout(), DisableMouseCapture)?;
+        }
+        result
+    }
+    /// Draw the frames of the emulation thread as they come, and handle
+    /// the input in between.
+    fn main_loop(&mut self, terminal: &mut DefaultTerminal) -> Result<()> {
         self.running = true;
-        while self.running {
-            // Tell the terminal to refresh its frame.
-            // In order to do so call self.draw(frame)
-            // to actually render the content.
-            // If an error occurs propagate the error.
-            terminal.draw(|frame: &mut Frame<'_>| self.draw(frame))?;
+        let emulation = self.emulation.clone();
+        self.sync_emulation(&mut Emulation::lock(&emulation));
+        let thread = EmulationThread::spawn(self.emulation.clone(), self.video.clone());
+        let result = self.draw_loop(terminal, &thread);
+        thread.stop();
+        result
+    }
+    fn draw_loop(
+        &mut self,
+        terminal: &mut DefaultTerminal,
+        thread: &EmulationThread,
+    ) -> Result<()> {
+        let shared = self.emulation.clone();
+        // The thread only ends early on a panic, passed on when stopped.
+        while self.running && !thread.is_finished() {
             self.handle_crossterm_events()?;
+            // Locked once per iteration, the emulation thread waits on it
+            // between frames.
+            let mut emulation = Emulation::lock(&shared);
+            self.sync_emulation(&mut emulation);
+            let (frame, speed) = self
+                .video
+                .lock()
+                .map_or((0, 0.), |video| (video.number, video.speed));
+            let now = Instant::now();
+            // Redraws for the input are held to the same budget.
+            if self.frameskip.is_due(frame, speed)
+                || self.redraw && self.frameskip.is_redraw_due(now, speed)
+            {
+                // Tell the terminal to refresh its frame.
+                // In order to do so call self.draw(frame)
+                // to actually render the content.
+                // If an error occurs propagate the error.
+                terminal.draw(|frame: &mut Frame<'_>| self.draw(frame, &emulation))?;
+                // Timed with the output to the terminal, the slow part.
+                self.frameskip.drawn(frame, now, now.elapsed());
+                self.redraw = false;
+            }
+            let dirty = emulation.consoles[0]
+                .cartridge
+                .as_ref()
+                .is_some_and(Cartridge::is_dirty);
+            drop(emulation);
+            self.save_printouts();
+            self.save_periodically(dirty);
         }
         Ok(())
     }
+    /// Pass the input, the speed and rewinding to the emulation thread,
+    /// and take its log messages.
+    fn sync_emulation(&mut self, emulation: &mut Emulation) {
+        let joypad = &mut emulation.consoles[self.focus].joypad;
+        for button in Button::ALL {
+            if self.pressed.contains(&button) {
+                joypad.press(button);
+            } else {
+                joypad.release(button);
+            }
+        }
+        // The other joypads of a Super Game Boy.
+        for player in 1..MAX_PLAYERS {
+            for button in Button::ALL {
+                if self.held_buttons.is_held(Action::Player(player, button)) {
+                    joypad.press_player(player, button);
+                } else {
+                    joypad.release_player(player, button);
+                }
+            }
+        }
+        emulation.speed = self.speed();
+        // Linked consoles cannot go back in time on their own, so only a
+        // single console rewinds.
+        emulation.rewinding =
+            emulation.consoles.len() == 1 && self.held_buttons.is_held(Action::Rewind);
+        for message in emulation.messages.drain(..) {
+            self.logs.append(message);
+        }
+    }
+    /// Multiplier of the Game Boy speed asked for, `None` when uncapped.
+    fn speed(&self) -> Option<f32> {
+        if self.fast_forward || self.held_buttons.is_held(Action::FastForward) {
+            self.speed_config.fast_forward
+        } else if self.slow_motion {
+            Some(self.speed_config.slow_motion)
+        } else {
+            Some(1.)
+        }
+    }
+    /// Save the state of the first console in `slot`.
+    fn save_state(&mut self, slot: usize) {
+        let Some(rom_path) = &self.rom_path else {
+            self.logs.append(LogMessage::new(
+                LogLevel::Warning,
+                "No cartridge loaded, nothing to save",
+            ));
+            return;
+        };
+        let path = state_path(rom_path, slot);
+        let emulation = Emulation::lock(&self.emulation);
+        let state = emulation.consoles[0].save_state(&emulation.lcd(), unix_time());
+        drop(emulation);
+        let message = match std::fs::write(&path, state) {
+            Ok(()) => LogMessage::new(LogLevel::Info, format!("State saved in slot {slot}")),
+            Err(error) => LogMessage::new(
+                LogLevel::Error,
+                format!("Could not save the state to {}: {error}", path.display()),
+            ),
+        };
+        self.logs.append(message);
+    }
+    /// Restore the state of the first console from `slot`.
+    fn load_state(&mut self, slot: usize) {
+        let Some(rom_path) = &self.rom_path else {
+            self.logs.append(LogMessage::new(
+                LogLevel::Warning,
+                "No cartridge loaded, nothing to load",
+            ));
+            return;
+        };
+        let path = state_path(rom_path, slot);
+        let result = std::fs::read(&path)
+            .map_err(|error| error.to_string())
+            .and_then(|state| {
+                Emulation::lock(&self.emulation).consoles[0]
+                    .load_state(&state)
+                    .map_err(|error| error.to_string())
+            });
+        let message = match result {
+            Ok(_) => LogMessage::new(LogLevel::Info, format!("State loaded from slot {slot}")),
+            Err(error) => LogMessage::new(
+                LogLevel::Error,
+                format!("Could not load slot {slot}: {error}"),
+            ),
+        };
+        self.logs.append(message);
+    }
+    fn on_browser_action(&mut self, action: BrowserAction) {
+        let Some(rom_path) = self.rom_path.clone() else {
+            return;
+        };
+        let result = match action {
+            BrowserAction::Load(slot) => {
+                self.load_state(slot);
+                self.state_browser = None;
+                return;
+            }
+            BrowserAction::Close => {
+                self.state_browser = None;
+                return;
+            }
+            BrowserAction::Delete(slot) => std::fs::remove_file(state_path(&rom_path, slot))
+                .map(|()| format!("State in slot {slot} deleted"))
+                .map_err(|error| error.to_string()),
+            BrowserAction::Rename(slot, name) => {
+                let path = state_path(&rom_path, slot);
+                std::fs::read(&path)
+                    .map_err(|error| error.to_string())
+                    .and_then(|state| {
+                        rename_state(&state, &name).map_err(|error| error.to_string())
+                    })
+                    .and_then(|state| {
+                        std::fs::write(&path, state).map_err(|error| error.to_string())
+                    })
+                    .map(|()| format!("State in slot {slot} named \"{name}\""))
+            }
+        };
+        let message = match result {
+            Ok(message) => LogMessage::new(LogLevel::Info, message),
+            Err(error) => LogMessage::new(LogLevel::Error, error),
+        };
+        self.logs.append(message);
+        if let Some(browser) = &mut self.state_browser {
+            browser.rescan(&rom_path);
+        }
+    }
+    fn save_printouts(&mut self) {
+        let Some(printouts) = self
+            .printer
+            .as_ref()
+            .and_then(|printer| printer.lock().ok())
+            .map(|mut printer| printer.take_printouts())
+        else {
+            return;
+        };
+        let seconds = unix_time();
+        for printout in &printouts {
+            // Numbered for the whole session, files already there are kept.
+            let path = loop {
+                let name = format!("jade_print_{seconds}_{}.png", self.printouts);
+                self.printouts += 1;
+                let path = self.print_dir.join(name);
+                if !path.exists() {
+                    break path;
+                }
+            };
+            let message = match write_png(printout, &path) {
+                Ok(()) => LogMessage::new(
+                    LogLevel::Info,
+                    format!("Printout saved to {}", path.display()),
+                ),
+                Err(error) => LogMessage::new(
+                    LogLevel::Error,
+                    format!("Could not save printout to {}: {error}", path.display()),
+                ),
+            };
+            self.logs.append(message);
+        }
+    }
     /// Draw the current `frame` to screen.
-    fn draw(&mut self, frame: &mut Frame) {
-        frame.render_widget(self, frame.area());
+    fn draw(&mut self, frame: &mut Frame, emulation: &Emulation) {
+        self.render(emulation, frame.area(), frame.buffer_mut());
     }
     /// Handle the incoming events.
+    ///
+    /// Waits at most `POLL_INTERVAL`, or until the next button auto-release.
     fn handle_crossterm_events(&mut self) -> Result<()> {
-        match event::read()? {
-            Event::Key(key) if key.kind == KeyEventKind::Press => self.on_key_event(key),
-            Event::Mouse(_) => {}
-            Event::Resize(_, _) => {}
-            _ => {}
+        let timeout = self
+            .held_buttons
+            .next_expiry(Instant::now())
+            .map_or(POLL_INTERVAL, |expiry| expiry.min(POLL_INTERVAL));
+        if event::poll(timeout)? {
+            self.redraw = true;
+            match event::read()? {
+                Event::Key(key) if key.kind == KeyEventKind::Press => self.on_key_event(key),
+                Event::Key(key) if key.kind == KeyEventKind::Release => self.on_key_release(key),
+                Event::Mouse(mouse) if self.show_gamepad => self.on_mouse_event(mouse),
+                Event::Resize(_, _) => {}
+                _ => {}
+            }
         }
+        let now = Instant::now();
+        for action in self.held_buttons.expire(now) {
+            if let Action::Turbo(button) = action {
+                self.turbo.stop(button);
+            }
+        }
+        self.update_joypad(now);
         Ok(())
     }
     fn on_key_event(&mut self, key: KeyEvent) {
+        if let (Some(browser), false) = (
+            &mut self.state_browser,
+            key.modifiers == KeyModifiers::CONTROL && key.code == KeyCode::Char('c'),
+        ) {
+            if let Some(action) = browser.on_key(key.code) {
+                self.on_browser_action(action);
+            }
+            return;
+        }
         match (key.modifiers, key.code) {
             (_, KeyCode::Esc | KeyCode::Char('q'))
             | (KeyModifiers::CONTROL, KeyCode::Char('c') | KeyCode::Char('C')) => self.quit(),
+            (modifiers, KeyCode::F(key)) if (1..=STATE_SLOTS).contains(&(key as usize)) => {
+                if modifiers.contains(KeyModifiers::SHIFT) {
+                    self.save_state(key as usize);
+                } else {
+                    self.load_state(key as usize);
+                }
+            }
+            (_, code) if !self.is_playing_gbs() && self.key_map.action(code).is_some() => {
+                if let Some(action) = self.key_map.action(code) {
+                    self.on_action(action, Instant::now());
+                }
+            }
+            (_, KeyCode::Char('b')) => {
+                self.state_browser = self.rom_path.as_deref().map(StateBrowser::scan);
+            }
+            (_, KeyCode::Char('t')) => self.show_tracker = !self.show_tracker,
+            (_, KeyCode::Char('g')) => self.toggle_gamepad(),
+            (_, KeyCode::Char('v')) => self.frameskip.cycle(),
+            (_, KeyCode::Char('r')) => self.toggle_vgm_recording(),
+            (_, KeyCode::Tab) if self.console_count() > 1 => self.cycle_focus(),
+            (_, KeyCode::PageUp) => self.scroll_paper(-PAPER_SCROLL),
+            (_, KeyCode::PageDown) => self.scroll_paper(PAPER_SCROLL),
+            (_, KeyCode::Char('l')) if self.is_recording_vgm() => {
+                Emulation::lock(&self.emulation).consoles[0]
+                    .apu
+                    .mark_vgm_loop();
+                self.logs
+                    .append(LogMessage::new(LogLevel::Info, "VGM loop point marked"));
+            }
+            (_, KeyCode::Right | KeyCode::Char('n')) => {
+                if let Some(gbs) = &mut Emulation::lock(&self.emulation).gbs {
+                    gbs.next_track();
+                }
+            }
+            (_, KeyCode::Left | KeyCode::Char('p')) => {
+                if let Some(gbs) = &mut Emulation::lock(&self.emulation).gbs {
+                    gbs.previous_track();
+                }
+            }
+            _ => {}
+        }
+    }
+    fn on_action(&mut self, action: Action, now: Instant) {
+        match action {
+            Action::Button(_) | Action::Player(..) | Action::Rewind | Action::FastForward => {
+                self.held_buttons.press(action, now);
+            }
+            Action::Turbo(button) => {
+                self.held_buttons.press(action, now);
+                self.turbo.start(button, now);
+            }
+            Action::RecordMacro => self.macros.toggle_recording(now),
+            Action::Macro(slot) => self.macros.select(slot, now),
+            Action::ToggleFastForward => self.fast_forward = !self.fast_forward,
+            Action::ToggleSlowMotion => self.slow_motion = !self.slow_motion,
+        }
+    }
+    fn on_mouse_event(&mut self, mouse: MouseEvent) {
+        match mouse.kind {
+            MouseEventKind::Down(MouseButton::Left) | MouseEventKind::Drag(MouseButton::Left) => {
+                self.gamepad.click(mouse.column, mouse.row)
+            }
+            MouseEventKind::Up(MouseButton::Left) => self.gamepad.release_click(),
+            MouseEventKind::Down(MouseButton::Right) => {
+                self.gamepad.toggle_latch(mouse.column, mouse.row)
+            }
             _ => {}
         }
     }
+    /// Show or hide the virtual gamepad, capturing the mouse while shown.
+    fn toggle_gamepad(&mut self) {
+        self.show_gamepad = !self.show_gamepad;
+        let result = if self.show_gamepad {
+            execute!(stdout(), EnableMouseCapture)
+        } else {
+            self.gamepad = VirtualGamepad::default();
+            execute!(stdout(), DisableMouseCapture)
+        };
+        if let Err(error) = result {
+            self.logs.append(LogMessage::new(
+                LogLevel::Error,
+                format!("Could not change the mouse capture: {error}"),
+            ));
+        }
+    }
+    fn on_key_release(&mut self, key: KeyEvent) {
+        if let Some(action) = self.key_map.action(key.code) {
+            if self.held_buttons.release(action) {
+                if let Action::Turbo(button) = action {
+                    self.turbo.stop(button);
+                }
+            }
+        }
+    }
+    /// Press the buttons held from the keyboard, by turbo or by the macro
+    /// being replayed, and release the others. Macros and turbo only play
+    /// on the first joypad.
+    fn update_joypad(&mut self, now: Instant) {
+        let mut keyboard_buttons: HashSet<Button> = self
+            .held_buttons
+            .iter()
+            .filter_map(|action| match action {
+                Action::Button(button) => Some(button),
+                _ => None,
+            })
+            .collect();
+        keyboard_buttons.extend(self.turbo.pressed(now));
+        keyboard_buttons.extend(self.gamepad.pressed());
+        for button in Button::ALL {
+            let pressed = keyboard_buttons.contains(&button);
+            if pressed != self.keyboard_buttons.contains(&button) {
+                self.macros.record(button, pressed, now);
+            }
+        }
+        let mut pressed = self.macros.pressed(now);
+        pressed.extend(&keyboard_buttons);
+        self.keyboard_buttons = keyboard_buttons;
+        self.gamepad.highlight(pressed.clone());
+        self.pressed = pressed;
+    }
+    fn scroll_paper(&mut self, rows: isize) {
+        if let Some(printer) = self
+            .printer
+            .as_ref()
+            .and_then(|printer| printer.lock().ok())
+        {
+            self.paper_view.scroll(rows, printer.paper());
+        }
+    }
+    /// Give the input to the next console, releasing the buttons of the
+    /// previous one.
+    fn cycle_focus(&mut self) {
+        let mut emulation = Emulation::lock(&self.emulation);
+        for button in Button::ALL {
+            emulation.consoles[self.focus].joypad.release(button);
+        }
+        self.focus = (self.focus + 1) % emulation.consoles.len();
+        drop(emulation);
+        self.logs.append(LogMessage::new(
+            LogLevel::Info,
+            format!("Controlling player {}", self.focus + 1),
+        ));
+    }
+    fn console_count(&self) -> usize {
+        Emulation::lock(&self.emulation).consoles.len()
+    }
+    fn is_playing_gbs(&self) -> bool {
+        Emulation::lock(&self.emulation).gbs.is_some()
+    }
+    fn is_recording_vgm(&self) -> bool {
+        Emulation::lock(&self.emulation).consoles[0]
+            .apu
+            .is_recording_vgm()
+    }
     fn quit(&mut self) {
+        if self.is_recording_vgm() {
+            self.toggle_vgm_recording();
+        }
         self.running = false;
     }
+    /// Start logging the sound register writes, or save the current log.
+    fn toggle_vgm_recording(&mut self) {
+        let mut emulation = Emulation::lock(&self.emulation);
+        let Some(vgm) = emulation.consoles[0].apu.stop_vgm() else {
+            emulation.consoles[0].apu.start_vgm();
+            drop(emulation);
+            self.logs
+                .append(LogMessage::new(LogLevel::Info, "VGM recording started"));
+            return;
+        };
+        drop(emulation);
+        let seconds = unix_time();
+        // Numbered like the printouts, files already there are kept.
+        let path = loop {
+            let path = PathBuf::from(format!("jade_{seconds}_{}.vgm", self.recordings));
+            self.recordings += 1;
+            if !path.exists() {
+                break path;
+            }
+        };
+        let message = match std::fs::write(&path, vgm) {
+            Ok(()) => LogMessage::new(
+                LogLevel::Info,
+                format!("VGM recording saved to {}", path.display()),
+            ),
+            Err(error) => LogMessage::new(
+                LogLevel::Error,
+                format!(
+                    "Could not save VGM recording to {}: {error}",
+                    path.display()
+                ),
+            ),
+        };
+        self.logs.append(message);
+    }
+    /// Render the emulated screens side by side, or the track list when
+    /// playing a GBS rip.
+    fn render_screen(
+        &self,
+        emulation: &Emulation,
+        video: &VideoFrame,
+        area: ratatui::prelude::Rect,
+        buf: &mut ratatui::prelude::Buffer,
+    ) {
+        if let Some(browser) = &self.state_browser {
+            browser.render(area, buf);
+            return;
+        }
+        if let Some(gbs) = &emulation.gbs {
+            GbsPlayerView::new(gbs).render(area, buf);
+            return;
+        }
+        let consoles = emulation.consoles.len();
+        if consoles == 1 {
+            render_console(video, 0, None, area, buf);
+            return;
+        }
+        // Two screens side by side, more in rows of two.
+        let rows = consoles.div_ceil(2);
+        let screens = Layout::vertical(vec![Constraint::Fill(1); rows])
+            .split(area)
+            .iter()
+            .flat_map(|row| {
+                Layout::horizontal([Constraint::Fill(1); 2])
+                    .split(*row)
+                    .to_vec()
+            })
+            .collect::<Vec<_>>();
+        for (player, screen_space) in screens.iter().take(consoles).enumerate() {
+            let mut title = format!("Player {}", player + 1);
+            if player == self.focus {
+                title.push_str(" ●");
+            }
+            render_console(video, player, Some(title), *screen_space, buf);
+        }
+    }
+}
+/// Render the screen of the `player` console from the last frame.
+fn render_console(
+    video: &VideoFrame,
+    player: usize,
+    title: Option<String>,
+    area: ratatui::prelude::Rect,
+    buf: &mut ratatui::prelude::Buffer,
+) {
+    let mut screen = match video.screens.get(player) {
+        Some((pixels, width)) if !pixels.is_empty() => Screen::new(pixels, *width),
+        _ => Screen::default(),
+    };
+    if let Some(title) = title {
+        screen = screen.title(title);
+    }
+    screen.render(area, buf);
+}
+/// Seconds since the UNIX epoch.
+fn unix_time() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
 }
 // This allows to encapsulate code related to rendering only on one place.
-impl Widget for &mut UserInterface {
-    fn render(self, area: ratatui::prelude::Rect, buf: &mut ratatui::prelude::Buffer) {
+impl UserInterface {
+    /// Render the whole interface, with `emulation` locked by the caller.
+    fn render(
+        &mut self,
+        emulation: &Emulation,
+        area: ratatui::prelude::Rect,
+        buf: &mut ratatui::prelude::Buffer,
+    ) {
+        // Taken with the emulation locked, as the emulation thread does.
+        let video = self
+            .video
+            .lock()
+            .map(|video| video.clone())
+            .unwrap_or_default();
         // Render the border with instructions.
         let title = Line::from(" Jade ").bold().green().centered();
-        let instructions =
-            Line::from(vec![" Quit ".into(), "<Q / Ctrl-c / Esc> ".green().bold()]).centered();
+        let mut instructions = vec![
+            " Tracker ".into(),
+            "<T>".green().bold(),
+            " Gamepad ".into(),
+            "<G>".green().bold(),
+            " Record VGM ".into(),
+            "<R>".green().bold(),
+            " Loop ".into(),
+            "<L>".green().bold(),
+        ];
+        if emulation.consoles.len() > 1 {
+            instructions.extend([" Player ".into(), "<Tab>".green().bold()]);
+        }
+        if self.rom_path.is_some() {
+            instructions.extend([
+                " Load/Save state ".into(),
+                "<F1-F10/Shift>".green().bold(),
+                " States ".into(),
+                "<B>".green().bold(),
+            ]);
+        }
+        // The keys of `[rewind]` and `[speed]`, as bound.
+        let mut bound = |label: &'static str, actions: &[Action]| {
+            let keys: Vec<String> = actions
+                .iter()
+                .filter_map(|&action| self.key_map.key(action).map(key_name))
+                .collect();
+            if !keys.is_empty() {
+                let keys = format!("<{}>", keys.join("/"));
+                instructions.extend([label.into(), keys.green().bold()]);
+            }
+        };
+        if emulation.consoles.len() == 1 {
+            bound(" Rewind ", &[Action::Rewind]);
+        }
+        bound(
+            " Fast-forward ",
+            &[Action::FastForward, Action::ToggleFastForward],
+        );
+        bound(" Slow motion ", &[Action::ToggleSlowMotion]);
+        instructions.extend([" Frameskip ".into(), "<V>".green().bold()]);
+        if self.printer.is_some() {
+            instructions.extend([" Paper ".into(), "<PgUp/PgDn>".green().bold()]);
+        }
+        instructions.extend([" Quit ".into(), "<Q / Ctrl-c / Esc> ".green().bold()]);
+        let instructions = Line::from(instructions).centered();
+        let now = Instant::now();
+        let status = match self.macros.status(now) {
+            Some(status) => Some(status),
+            None if emulation.rewinding => {
+                Some(format!("Rewinding {:.1} s", emulation.rewind.seconds()))
+            }
+            None if self.turbo.is_active() => Some(format!("Turbo {:.0}/s", self.turbo.rate())),
+            None => None,
+        };
+        let speed = match self.speed() {
+            Some(1.) => "",
+            Some(speed) if speed > 1. => "Fast-forward ",
+            Some(_) => "Slow motion ",
+            None => "Uncapped ",
+        };
+        let skip = self.frameskip.skip(video.speed);
+        let frameskip = match (self.frameskip.is_automatic(), skip) {
+            (true, 0) => String::new(),
+            (true, skip) => format!("Frameskip auto {skip} "),
+            (false, skip) => format!("Frameskip {skip} "),
+        };
+        let speed = format!(" {speed}{:.0}% {frameskip}", video.speed * 100.);
         Block::bordered()
             .border_type(BorderType::Thick)
             // .border_type(BorderType::Rounded)
             .title(title)
+            .title(Line::from(speed).cyan().bold().left_aligned())
+            .title(
+                Line::from(
+                    status
+                        .map(|status| format!(" {status} "))
+                        .unwrap_or_default(),
+                )
+                .yellow()
+                .bold()
+                .right_aligned(),
+            )
             .title_bottom(instructions.centered())
             .render(area, buf);
 
@@ -100,7 +1077,33 @@ impl Widget for &mut UserInterface {
             ],
         )
         .areas(screen_space);
-        Screen.render(screen_space, buf);
+        let mut constraints = vec![Constraint::Min(1)];
+        if self.printer.is_some() {
+            constraints.push(Constraint::Percentage(30));
+        }
+        if self.show_tracker {
+            constraints.push(Constraint::Percentage(40));
+        }
+        if self.show_gamepad {
+            constraints.push(Constraint::Length(GAMEPAD_WIDTH));
+        }
+        let panes = Layout::horizontal(constraints).split(screen_space);
+        self.render_screen(emulation, &video, panes[0], buf);
+        let mut panes = panes.iter().skip(1);
+        if let Some(printer) = &self.printer {
+            if let (Ok(printer), Some(paper_space)) = (printer.lock(), panes.next()) {
+                self.paper_view.render(printer.paper(), *paper_space, buf);
+            }
+        }
+        if let (true, Some(tracker_space)) = (self.show_tracker, panes.next()) {
+            emulation.tracker.render(*tracker_space, buf);
+        }
+        if let (true, Some(gamepad_space)) = (self.show_gamepad, panes.next()) {
+            let [gamepad_space, _] =
+                Layout::vertical([Constraint::Length(GAMEPAD_HEIGHT), Constraint::Min(0)])
+                    .areas(*gamepad_space);
+            self.gamepad.render(gamepad_space, buf);
+        }
 
         // Render the logs
         let [_, logs_space, _] = Layout::new(
@@ -113,33 +1116,6 @@ impl Widget for &mut UserInterface {
             ],
         )
         .areas(logs_space);
-        self.logs.append(LogMessage::new(
-            LogLevel::Info,
-            format!(
-                "Screen space area {}, width {}, height {}",
-                screen_space.area(),
-                screen_space.width,
-                screen_space.height,
-            ),
-        ));
-        self.logs.append(LogMessage::new(
-            LogLevel::Info,
-            format!(
-                "Screen Size{}, x {}, y {}",
-                screen_space.as_size(),
-                screen_space.x,
-                screen_space.y
-            ),
-        ));
-        self.logs.append(LogMessage::new(
-            LogLevel::Info,
-            format!(
-                "Area {}, center_x {}, center_y {}",
-                screen_space.area(),
-                screen_space.x + screen_space.width,
-                screen_space.y + screen_space.height
-            ),
-        ));
         self.logs.render(logs_space, buf);
     }
 }